@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::puzzle::{Invalid, Puzzle};
+use crate::water::Water;
+
+/// What an unknown cell could still turn out to be
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Candidates {
+    /// Colours seen so far that are short of filling their tubes
+    pub colours: BTreeSet<Water>,
+    /// A colour that hasn't been seen anywhere yet
+    pub unseen: bool,
+}
+
+impl Candidates {
+    /// The colour this has to be, if there is only one
+    fn only(&self) -> Option<Water> {
+        match (self.colours.len(), self.unseen) {
+            (1, false) => self.colours.first().copied(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Candidates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<_> = self.colours.iter().map(|w| format!("{w:?}")).collect();
+        if self.unseen {
+            names.push("a colour not seen yet".to_owned());
+        }
+        match names.split_last() {
+            None => write!(f, "nothing"),
+            Some((last, [])) => write!(f, "{last}"),
+            Some((last, rest)) => write!(f, "{} or {last}", rest.join(", ")),
+        }
+    }
+}
+
+pub struct Deduction {
+    /// Cells that can only be one colour
+    pub forced: Vec<((usize, usize), Water)>,
+    /// Every other unknown cell
+    pub open: Vec<((usize, usize), Candidates)>,
+}
+
+/// Works out what the unknown cells of `p` can be from how many
+/// of each colour it takes to fill whole tubes.
+///
+/// Every unknown cell starts out as any colour that is short, or one
+/// not seen yet if there are more unknown cells than that. A colour
+/// with as many places left to go as it is short goes in all of them,
+/// and once a colour has all its cells it is no longer a candidate
+/// anywhere else. That is repeated until nothing changes.
+pub fn deduce(p: &Puzzle) -> Result<Deduction, Invalid> {
+    p.validate()?;
+    let mut left = p.shortfall();
+    let cells = p.unknown_cells();
+    let unseen = cells.len() - left.values().sum::<usize>();
+    let start = Candidates {
+        colours: left.keys().copied().collect(),
+        unseen: unseen > 0,
+    };
+    let mut open: BTreeMap<_, _> = cells.into_iter().map(|c| (c, start.clone())).collect();
+    let mut forced = vec![];
+
+    loop {
+        let mut decided = vec![];
+        for (&w, &n) in &left {
+            let places: Vec<_> = open
+                .iter()
+                .filter(|(_, c)| c.colours.contains(&w))
+                .map(|(cell, _)| *cell)
+                .collect();
+            if places.len() == n {
+                decided.extend(places.into_iter().map(|cell| (cell, w)));
+            }
+        }
+        decided.extend(
+            open.iter()
+                .filter_map(|(cell, c)| c.only().map(|w| (*cell, w))),
+        );
+        decided.sort_unstable();
+        decided.dedup_by_key(|(cell, _)| *cell);
+        if decided.is_empty() {
+            break;
+        }
+
+        for (cell, w) in decided {
+            open.remove(&cell);
+            forced.push((cell, w));
+            if let Some(n) = left.get_mut(&w) {
+                *n -= 1;
+            }
+        }
+        left.retain(|_, n| *n > 0);
+        for c in open.values_mut() {
+            c.colours.retain(|w| left.contains_key(w));
+        }
+    }
+
+    forced.sort_unstable();
+    Ok(Deduction {
+        forced,
+        open: open.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod deduce_test {
+    use super::*;
+    use crate::state::State::{Empty, Unknown, Water};
+    use crate::water::{BLUE, GREEN, RED};
+
+    #[test]
+    fn test_deduce_forced() {
+        let mut p = Puzzle::new(5);
+        p.set_whole_tube(0, [Water(GREEN), Water(BLUE), Water(RED), Unknown]);
+        p.set_whole_tube(1, [Water(BLUE), Water(BLUE), Water(RED), Water(GREEN)]);
+        p.set_whole_tube(2, [Water(BLUE), Water(RED), Water(GREEN), Water(GREEN)]);
+
+        let Ok(d) = deduce(&p) else {
+            panic!("puzzle is valid");
+        };
+        assert_eq!(d.forced, vec![((0, 3), RED)]);
+        assert!(d.open.is_empty());
+    }
+
+    #[test]
+    fn test_deduce_open() {
+        let mut p = Puzzle::new(5);
+        p.set_whole_tube(0, [Water(GREEN), Water(BLUE), Unknown, Unknown]);
+        p.set_whole_tube(1, [Water(BLUE), Water(BLUE), Water(RED), Water(GREEN)]);
+        p.set_whole_tube(2, [Water(BLUE), Water(RED), Water(GREEN), Water(GREEN)]);
+        p.set_whole_tube(3, [Empty, Unknown, Unknown, Unknown]);
+        p.set_whole_tube(4, [Empty, Empty, Empty, Unknown]);
+
+        let Ok(d) = deduce(&p) else {
+            panic!("puzzle is valid");
+        };
+        // RED is two short, and four cells are some new colour
+        assert!(d.forced.is_empty());
+        assert_eq!(d.open.len(), 6);
+        let expected = Candidates {
+            colours: BTreeSet::from([RED]),
+            unseen: true,
+        };
+        assert!(d.open.iter().all(|(_, c)| *c == expected));
+        assert_eq!(expected.to_string(), "Red or a colour not seen yet");
+    }
+}
@@ -0,0 +1,32 @@
+//! Puzzles and helpers shared by the tests of several modules
+
+use crate::puzzle::Puzzle;
+use crate::solve::NoSolution;
+use crate::state::State::Water;
+use crate::water::{BLUE, GREEN, RED};
+
+/// Three mixed tubes and two empty ones, solved in a few moves
+pub fn test_puzzle() -> Puzzle {
+    let mut p = Puzzle::new(5);
+    p.set_whole_tube(0, [Water(GREEN), Water(BLUE), Water(RED), Water(GREEN)]);
+    p.set_whole_tube(1, [Water(BLUE), Water(BLUE), Water(RED), Water(GREEN)]);
+    p.set_whole_tube(2, [Water(BLUE), Water(RED), Water(RED), Water(GREEN)]);
+    p
+}
+
+/// What a solver found for a puzzle that has a solution
+pub fn solved<T>(result: Result<T, NoSolution>) -> T {
+    let Ok(found) = result else {
+        panic!("puzzle should be solvable");
+    };
+    found
+}
+
+/// `p` after `moves`, which all have to be valid
+pub fn replay(p: &Puzzle, moves: &[(u8, u8)]) -> Puzzle {
+    let mut p = p.clone();
+    for m in moves {
+        p.pour(m.0.into(), m.1.into()).unwrap();
+    }
+    p
+}
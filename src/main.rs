@@ -1,22 +1,47 @@
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::time::Duration;
+
 use rustyline::{self, error::ReadlineError, Editor};
 
+use puzzle::ValidMoves;
 use repl::{Error, Usage};
-use solve::{dfs_puzzle, NoSolution};
+use solve::{
+    astar_puzzle, dfs_puzzle, Budget, Cancel, MoveOrder, NoSolution, Plan, Progress, Search,
+    Solutions, STRATEGIES,
+};
 use water::Water;
 
+mod deduce;
+#[cfg(test)]
+mod fixtures;
+mod odds;
+mod optimise;
+mod packed;
+mod palette;
 mod puzzle;
+mod rate;
 mod repl;
 mod solve;
 mod state;
 mod tube;
 mod water;
 
+/// What the REPL keeps between commands, besides the puzzle
+#[derive(Default)]
+struct Session {
+    cancel: Cancel,
+    /// The last solution found, and the strategy that found it
+    plan: Option<(String, Plan)>,
+}
+
 fn load_file(puzzle: &mut puzzle::Puzzle, args: &[&str]) -> Result<(), Error> {
     let file = args.first().ok_or(Error::Usage(Usage::Load))?;
     let json = std::fs::read_to_string(file)?;
     let loaded_puzzle = serde_json::from_str::<puzzle::Puzzle>(&json)?;
     puzzle.reset(loaded_puzzle);
-    Ok(println!("{puzzle}"))
+    println!("{puzzle}");
+    Ok(())
 }
 
 fn quick_tube(puzzle: &mut puzzle::Puzzle, args: &[&str]) -> Result<(), Error> {
@@ -31,6 +56,9 @@ fn quick_tube(puzzle: &mut puzzle::Puzzle, args: &[&str]) -> Result<(), Error> {
         if tube >= size {
             return Err(Error::InvalidTube(tube, size));
         }
+        if i.chars().count() - 1 > puzzle.capacity() {
+            return Err(Error::InvalidIndex(puzzle.capacity()));
+        }
         for (idx, j) in i.chars().skip(1).enumerate() {
             let c = Water::try_from(j.to_string().as_str())
                 .map_err(|e| Error::from_water(e, Usage::QuickTube))?;
@@ -40,41 +68,460 @@ fn quick_tube(puzzle: &mut puzzle::Puzzle, args: &[&str]) -> Result<(), Error> {
     Ok(())
 }
 
-fn process_command(puzzle: &mut puzzle::Puzzle, command: &str, args: &[&str]) -> Result<(), Error> {
+fn solve_puzzle(
+    puzzle: &puzzle::Puzzle,
+    session: &mut Session,
+    args: &[&str],
+) -> Result<(), Error> {
+    let cancel = &session.cancel;
+    let mut budget = Budget {
+        cancel: Some(cancel.clone()),
+        ..Budget::default()
+    };
+    let mut rest = vec![];
+    let mut stats = false;
+    let mut optimise = false;
+    let mut all = None;
+    let mut extra = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match *arg {
+            "--stats" => stats = true,
+            "--all" => {
+                // The count is optional, and no strategy is a number
+                let max = iter.as_slice().first().and_then(|s| s.parse().ok());
+                if max.is_some() {
+                    iter.next();
+                }
+                all = Some(max.filter(|&n| n > 0));
+            }
+            "--extra" => {
+                let max = iter.as_slice().first().and_then(|s| s.parse().ok());
+                if max.is_some() {
+                    iter.next();
+                }
+                extra = Some(max.unwrap_or(MAX_EXTRA_TUBES));
+            }
+            "--optimise" | "--optimize" => optimise = true,
+            "--timeout" => {
+                let timeout = iter
+                    .next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                    .ok_or(Error::Usage(Usage::Solve))?;
+                budget.timeout = Some(timeout);
+            }
+            "--nodes" => {
+                budget.max_nodes = Some(parse_int(iter.next()).ok_or(Error::Usage(Usage::Solve))?)
+            }
+            "--depth" => {
+                budget.max_depth = Some(parse_int(iter.next()).ok_or(Error::Usage(Usage::Solve))?)
+            }
+            a => rest.push(a),
+        }
+    }
+
+    // Enumerating solutions has its own way of searching
+    if all.is_some() && (!rest.is_empty() || extra.is_some()) {
+        return Err(Error::Usage(Usage::Solve));
+    }
+    let (name, rest) = rest.split_first().unwrap_or((&STRATEGIES[0].name, &[]));
+    let strategy = solve::strategy(name).ok_or_else(|| Error::UnknownStrategy(name.to_string()))?;
+    let solver = (strategy.build)(rest).ok_or(Error::Usage(Usage::Solve))?;
+    let key = std::iter::once(strategy.name)
+        .chain(rest.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    // Pours that followed the last solution leave nothing to search for
+    let planned = session
+        .plan
+        .as_ref()
+        .filter(|(k, _)| all.is_none() && *k == key)
+        .and_then(|(_, plan)| plan.rest(puzzle));
+    let following = planned.is_some();
+
+    cancel.reset();
+    // Only draw progress where it can be overwritten in place
+    let progress = !following && std::io::stderr().is_terminal();
+    let mut search = Search::new(budget);
+    if progress {
+        search = search.observe(Duration::from_millis(100), print_progress);
+    }
+    if let Some((_, plan)) = &session.plan {
+        search = search.towards(plan);
+    }
+    if let Some(max) = all {
+        let result = solve::all_solutions(puzzle, max, &mut search);
+        if progress {
+            eprint!("\r\x1b[K");
+        }
+        print_solutions(result, max.is_some());
+        if stats {
+            println!("{}", search.stats());
+        }
+        return Ok(());
+    }
+    let result = match (planned, extra) {
+        (Some(moves), _) => {
+            println!("following the last solution");
+            Ok((puzzle.clone(), moves))
+        }
+        (None, Some(max)) => solve::extra_tubes(puzzle, max, solver.as_ref(), &mut search),
+        (None, None) => solver
+            .solve(puzzle, &mut search)
+            .map(|moves| (puzzle.clone(), moves)),
+    };
+    if progress {
+        eprint!("\r\x1b[K");
+    }
+    let mut plan = None;
+    let result = result.map(|(solved, moves)| {
+        let added = solved.size() - puzzle.size();
+        match added {
+            0 => {}
+            1 => println!("needs 1 extra tube, numbered {}", puzzle.size()),
+            n => println!(
+                "needs {n} extra tubes, numbered {} to {}",
+                puzzle.size(),
+                solved.size() - 1
+            ),
+        }
+        let moves = if optimise {
+            let optimised = optimise::optimise(&solved, &moves);
+            println!("optimised {} -> {} moves", moves.0.len(), optimised.0.len());
+            optimised
+        } else {
+            moves
+        };
+        // Moves using tubes the puzzle doesn't have can't be followed,
+        // and the plan being followed already covers the rest
+        if added == 0 && !following {
+            plan = Plan::new(puzzle, &moves).map(|p| p.explored(search.take_explored()));
+        }
+        moves
+    });
+    print_solution(result);
+    if stats {
+        println!("{}", search.stats());
+    }
+    drop(search);
+    if let Some(plan) = plan {
+        session.plan = Some((key, plan));
+    }
+    Ok(())
+}
+
+/// Most empty tubes `solve --extra` adds unless told otherwise
+const MAX_EXTRA_TUBES: usize = 3;
+
+/// How long `hint` looks for an optimal solution
+/// before settling for any solution
+const HINT_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn hint(puzzle: &puzzle::Puzzle, cancel: &Cancel) -> Result<(), Error> {
+    cancel.reset();
+    let budget = Budget {
+        cancel: Some(cancel.clone()),
+        ..Budget::default()
+    };
+    let optimal = astar_puzzle(
+        puzzle,
+        &mut Search::new(Budget {
+            timeout: Some(HINT_TIMEOUT),
+            ..budget.clone()
+        }),
+    );
+    let found = match optimal {
+        Err(NoSolution::OutOfBudget(..)) if !cancel.is_cancelled() => {
+            dfs_puzzle(puzzle, MoveOrder::default(), &mut Search::new(budget))
+                .map(|moves| (optimise::optimise(puzzle, &moves), false))
+        }
+        optimal => optimal.map(|moves| (moves, true)),
+    };
+    let (moves, optimal) = match found {
+        Ok(found) => found,
+        Err(e) => {
+            print_solution(Err(e));
+            return Ok(());
+        }
+    };
+    let (from, to) = moves.0[0];
+    println!("{}", puzzle.highlight((from, to)));
+    let left = moves.0.len();
+    if optimal {
+        println!("pour {from} -> {to}. {left} moves left");
+    } else {
+        println!("pour {from} -> {to}. at most {left} moves left");
+    }
+    Ok(())
+}
+
+fn print_odds(puzzle: &puzzle::Puzzle, cancel: &Cancel, args: &[&str]) -> Result<(), Error> {
+    let mut samples = 100;
+    let mut nodes = 20_000;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let n = match *arg {
+            "--nodes" => &mut nodes,
+            _ => {
+                samples = arg.parse().map_err(|_| Error::Usage(Usage::Odds))?;
+                continue;
+            }
+        };
+        *n = parse_int(iter.next()).ok_or(Error::Usage(Usage::Odds))?;
+    }
+
+    cancel.reset();
+    let odds = odds::move_odds(puzzle, samples.max(1), nodes, Some(cancel))
+        .map_err(|reason| Error::Message(format!("cannot be solved... {reason}")))?;
+    let mut moves = odds.moves;
+    moves.sort_by_key(|m| std::cmp::Reverse(m.wins));
+    let total = odds.completions;
+    for m in &moves {
+        let p = m.probability(total) * 100.0;
+        print!("({}, {}) {p:5.1}%", m.played.0, m.played.1);
+        if m.undecided > 0 {
+            print!(" ({} undecided)", m.undecided);
+        }
+        println!();
+    }
+    let how = if odds.exhaustive { "all" } else { "sampled" };
+    println!("over {total} completions ({how})");
+    Ok(())
+}
+
+/// Fills in the unknown cells that can only be one colour,
+/// and says what the others could be
+fn deduce_cells(puzzle: &mut puzzle::Puzzle) -> Result<(), Error> {
+    let deduction = deduce::deduce(puzzle)
+        .map_err(|reason| Error::Message(format!("cannot be solved... {reason}")))?;
+    for &((tube, idx), w) in &deduction.forced {
+        puzzle.set_tube(tube, idx, state::State::Water(w));
+        println!("tube {tube} index {idx} is {w:?}");
+    }
+    if !deduction.forced.is_empty() {
+        println!("{puzzle}");
+    }
+    let mut open = BTreeMap::<_, Vec<_>>::new();
+    for (cell, candidates) in deduction.open {
+        open.entry(candidates).or_default().push(cell);
+    }
+    for (candidates, cells) in open {
+        let cells = cells
+            .iter()
+            .map(|(tube, idx)| format!("{tube}:{idx}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!("{cells} could be {candidates}");
+    }
+    if deduction.forced.is_empty() {
+        println!("nothing more can be deduced");
+    }
+    Ok(())
+}
+
+/// Most states `rate` explores unless told otherwise
+const RATE_STATES: usize = 200_000;
+
+/// Rates the current puzzle, a saved one, or every
+/// puzzle saved in a directory
+fn rate_puzzles(puzzle: &puzzle::Puzzle, cancel: &Cancel, args: &[&str]) -> Result<(), Error> {
+    let mut max_states = RATE_STATES;
+    let mut path = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match *arg {
+            "--states" => {
+                max_states = parse_int(iter.next())
+                    .filter(|&n| n > 0)
+                    .ok_or(Error::Usage(Usage::Rate))?;
+            }
+            p if path.is_none() => path = Some(p),
+            _ => return Err(Error::Usage(Usage::Rate)),
+        }
+    }
+
+    cancel.reset();
+    let Some(path) = path else {
+        match rate::rate(puzzle, max_states, Some(cancel)) {
+            Ok(rating) => println!("{rating}"),
+            Err(e) => print_solution(Err(e)),
+        }
+        return Ok(());
+    };
+    if !std::path::Path::new(path).is_dir() {
+        let loaded = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        match rate::rate(&loaded, max_states, Some(cancel)) {
+            Ok(rating) => println!("{rating}"),
+            Err(e) => print_solution(Err(e)),
+        }
+        return Ok(());
+    }
+
+    let mut files = std::fs::read_dir(path)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect::<Vec<_>>();
+    files.sort();
+    println!(
+        "{:<16} {:>7} {:>7} {:>9} {:>9} {:>9}",
+        "puzzle", "rating", "optimal", "states", "dead", "branching"
+    );
+    for file in files {
+        if cancel.is_cancelled() {
+            break;
+        }
+        let name = file
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        let loaded = std::fs::read_to_string(&file)
+            .map_err(Error::from)
+            .and_then(|json| Ok(serde_json::from_str::<puzzle::Puzzle>(&json)?));
+        let rating = match loaded {
+            Ok(p) => rate::rate(&p, max_states, Some(cancel)),
+            Err(e) => {
+                println!("{name:<16} {e}");
+                continue;
+            }
+        };
+        match rating {
+            Ok(r) => {
+                let score = r
+                    .score()
+                    .map_or_else(|| "-".to_owned(), |s| format!("{s:.1}"));
+                let optimal = r.optimal.map_or_else(|| "-".to_owned(), |n| n.to_string());
+                let partial = if r.complete { "" } else { "+" };
+                println!(
+                    "{name:<16} {score:>7} {optimal:>7} {:>9} {:>9} {:>9.3}",
+                    format!("{}{partial}", r.states),
+                    format!("{}{partial}", r.dead),
+                    r.branching,
+                );
+            }
+            Err(NoSolution::Invalid(reason)) => println!("{name:<16} {reason}"),
+            Err(NoSolution::HasUnknown(..)) => println!("{name:<16} has unknown cells"),
+            Err(NoSolution::AlreadySolved) => println!("{name:<16} already solved"),
+            Err(NoSolution::CannotBeSolved(..) | NoSolution::NothingToReveal) => {
+                println!("{name:<16} cannot be solved")
+            }
+            Err(NoSolution::OutOfBudget(_, limit)) => println!("{name:<16} gave up... {limit}"),
+        }
+    }
+    Ok(())
+}
+
+/// Lists the colours of the current palette, or switches to the
+/// built-in one or one read from a file. The puzzle keeps its
+/// colours by name, so the new palette needs all of them.
+fn use_palette(puzzle: &mut puzzle::Puzzle, args: &[&str]) -> Result<(), Error> {
+    let palette = match args {
+        [] => {
+            list_palette();
+            return Ok(());
+        }
+        ["default"] => palette::Palette::builtin(),
+        [file] => palette::Palette::from_json(&std::fs::read_to_string(file)?)
+            .map_err(|e| Error::from(&e))?,
+        _ => return Err(Error::Usage(Usage::Palette)),
+    };
+    let mut recoloured = puzzle.clone();
+    for (tube, t) in puzzle.tubes().iter().enumerate() {
+        for (idx, s) in t.cells().iter().enumerate() {
+            if let state::State::Water(w) = s {
+                let name = w.name().ok_or_else(|| {
+                    Error::Message(format!(
+                        "tube {tube} has colour {}, which isn't in the current palette",
+                        w.index()
+                    ))
+                })?;
+                let w = palette.find(&name).ok_or_else(|| {
+                    Error::Message(format!("the puzzle has {name}, which the palette doesn't"))
+                })?;
+                recoloured.set_tube(tube, idx, state::State::Water(w));
+            }
+        }
+    }
+    palette::set(palette);
+    puzzle.reset(recoloured);
+    list_palette();
+    Ok(())
+}
+
+fn list_palette() {
+    for (w, colour) in palette::current().colours() {
+        println!("{w} {:<10} {}", colour.name, colour.aliases.join(", "));
+    }
+}
+
+fn list_solvers() {
+    for s in STRATEGIES {
+        let name = std::iter::once(s.name)
+            .chain(s.aliases.iter().copied())
+            .collect::<Vec<_>>()
+            .join("|");
+        let usage = format!("{name} {}", s.args);
+        println!("{:<40}{}", usage.trim_end(), s.about);
+    }
+}
+
+fn print_progress(p: &Progress) {
+    eprint!(
+        "\r\x1b[K{:.1}s expanded: {} frontier: {} visited: {} depth: {}",
+        p.elapsed.as_secs_f64(),
+        p.expanded,
+        p.frontier,
+        p.visited,
+        p.depth,
+    );
+}
+
+fn process_command(
+    puzzle: &mut puzzle::Puzzle,
+    session: &mut Session,
+    command: &str,
+    args: &[&str],
+) -> Result<(), Error> {
     match command {
         "i" | "init" => {
             let size = parse_int(args.first()).ok_or(Error::Usage(Usage::Init))?;
+            let capacity = match args.get(1) {
+                Some(_) => parse_int(args.get(1)).ok_or(Error::Usage(Usage::Init))?,
+                None => tube::DEFAULT_CAPACITY,
+            };
+            if !(1..=tube::MAX_CAPACITY).contains(&capacity) {
+                return Err(Error::InvalidCapacity);
+            }
             (size > 2)
-                .then(|| puzzle.reset(puzzle::Puzzle::new(size)))
+                .then(|| puzzle.reset(puzzle::Puzzle::with_capacity(size, capacity)))
                 .ok_or(Error::InvalidPuzzleSize)
         }
         "load" => load_file(puzzle, args),
-        "solve" => Ok(match dfs_puzzle(puzzle) {
-            Ok(solution) => println!("{solution}"),
-            Err(NoSolution::AlreadySolved) => println!("already solved"),
-            Err(NoSolution::CannotBeSolved(moves, max_depth)) => {
-                println!("cannot be solved... max depth: {max_depth}");
-                println!("{moves}");
-            }
-            Err(NoSolution::HasUnknown(puzzle, moves)) => {
-                println!("cannot be solved... puzzle contains unknown");
-                println!("{puzzle}");
-                println!("{moves}");
-            }
-        }),
+        "solve" => solve_puzzle(puzzle, session, args),
+        "solvers" => {
+            list_solvers();
+            Ok(())
+        }
+        "h" | "hint" => hint(puzzle, &session.cancel),
+        "odds" => print_odds(puzzle, &session.cancel, args),
+        "deduce" => deduce_cells(puzzle),
+        "rate" => rate_puzzles(puzzle, &session.cancel, args),
         "save" => Ok(std::fs::write(
             args.first().ok_or(Error::Usage(Usage::Save))?,
             serde_json::to_string(puzzle)?,
         )?),
         "tt" => quick_tube(puzzle, args),
         "t" | "tube" => {
-            let size = puzzle.size();
+            let (size, capacity) = (puzzle.size(), puzzle.capacity());
             for w in args.windows(2).step_by(2) {
                 let [i, water]: [&str; 2] = w.try_into().or(Err(Error::Usage(Usage::Tube)))?;
                 let tube = i.parse::<usize>().or(Err(Error::Usage(Usage::Tube)))?;
                 if tube >= size {
                     return Err(Error::InvalidTube(tube, size));
                 }
+                if water.split(',').count() > capacity {
+                    return Err(Error::InvalidIndex(capacity));
+                }
                 for (idx, colour) in water.split(',').enumerate() {
                     let col =
                         Water::try_from(colour).map_err(|e| Error::from_water(e, Usage::Tube))?;
@@ -91,30 +538,140 @@ fn process_command(puzzle: &mut puzzle::Puzzle, command: &str, args: &[&str]) ->
                     .map_err(|e| Error::InvalidPour(e.from, e.to)),
                 (Some(tube), _) if tube >= size => Err(Error::InvalidTube(tube, size)),
                 (_, Some(tube)) if tube >= size => Err(Error::InvalidTube(tube, size)),
-                (_, Some(idx)) if idx >= 4 => Err(Error::InvalidIndex),
                 (_, _) => Err(Error::Usage(Usage::Pour)),
             }
         }
-        "u" | "unset" => set_tube(puzzle.size(), args, Usage::Unset, |tube, idx| {
-            puzzle.set_tube(tube, idx, state::State::Unknown);
-        }),
-        "e" | "empty" => set_tube(puzzle.size(), args, Usage::Empty, |tube, idx| {
-            puzzle.set_tube(tube, idx, state::State::Empty)
-        }),
+        "u" | "unset" => set_tube(
+            puzzle.size(),
+            puzzle.capacity(),
+            args,
+            Usage::Unset,
+            |tube, idx| {
+                puzzle.set_tube(tube, idx, state::State::Unknown);
+            },
+        ),
+        "e" | "empty" => set_tube(
+            puzzle.size(),
+            puzzle.capacity(),
+            args,
+            Usage::Empty,
+            |tube, idx| puzzle.set_tube(tube, idx, state::State::Empty),
+        ),
         "s" | "set" => {
             let colour =
                 Water::try_from(args.get(2)).map_err(|e| Error::from_water(e, Usage::Set))?;
-            set_tube(puzzle.size(), args, Usage::Set, |tube, idx| {
-                puzzle.set_tube(tube, idx, state::State::Water(colour));
-            })
+            set_tube(
+                puzzle.size(),
+                puzzle.capacity(),
+                args,
+                Usage::Set,
+                |tube, idx| {
+                    puzzle.set_tube(tube, idx, state::State::Water(colour));
+                },
+            )
+        }
+        "d" | "display" => {
+            println!("{puzzle}");
+            Ok(())
+        }
+        "v" | "valid" => {
+            println!("{}", puzzle.valid_moves());
+            Ok(())
+        }
+        "rule" => {
+            match args {
+                [] => {}
+                [rule] => {
+                    puzzle.set_pour_rule(tube::Pour::try_from(*rule).map_err(|e| Error::from(&e))?)
+                }
+                _ => return Err(Error::Usage(Usage::Rule)),
+            }
+            println!("pour rule: {}", puzzle.pour_rule());
+            Ok(())
+        }
+        "palette" => use_palette(puzzle, args),
+        "addtube" => {
+            let n = match args.first() {
+                Some(_) => parse_int(args.first()).ok_or(Error::Usage(Usage::AddTube))?,
+                None => 1,
+            };
+            for _ in 0..n {
+                puzzle.add_tube();
+            }
+            println!("{puzzle}");
+            Ok(())
+        }
+        "k" | "sticky" => {
+            let size = puzzle.size();
+            match parse_int(args.first()) {
+                Some(tube) if tube < size => {
+                    let sticky = !puzzle.tubes()[tube].is_sticky();
+                    puzzle.set_sticky(tube, sticky);
+                    println!(
+                        "tube {tube} is {}",
+                        if sticky { "sticky" } else { "no longer sticky" }
+                    );
+                    Ok(())
+                }
+                Some(tube) => Err(Error::InvalidTube(tube, size)),
+                None => Err(Error::Usage(Usage::Sticky)),
+            }
         }
-        "d" | "display" => Ok(println!("{puzzle}")),
-        "v" | "valid" => Ok(println!("{}", puzzle.valid_moves())),
         a => Err(Error::UnrecognizedCommand(a.to_owned())),
     }
 }
 
-fn process_line(puzzle: &mut puzzle::Puzzle, line: &str) -> Result<(), Error> {
+fn print_solution(result: Result<ValidMoves, NoSolution>) {
+    match result {
+        Ok(solution) => println!("{solution}"),
+        Err(NoSolution::AlreadySolved) => println!("already solved"),
+        Err(NoSolution::CannotBeSolved(moves, max_depth)) => {
+            println!("cannot be solved... max depth: {max_depth}");
+            println!("{moves}");
+        }
+        Err(NoSolution::HasUnknown(puzzle, moves)) => {
+            println!("cannot be solved... puzzle contains unknown");
+            println!("{puzzle}");
+            println!("{moves}");
+        }
+        Err(NoSolution::Invalid(reason)) => println!("cannot be solved... {reason}"),
+        Err(NoSolution::NothingToReveal) => {
+            println!("nothing hidden can be uncovered without getting stuck")
+        }
+        Err(NoSolution::OutOfBudget(moves, limit)) => {
+            println!("gave up... {limit}. closest so far:");
+            println!("{moves}");
+        }
+    }
+}
+
+/// Prints every solution found when `list` is set,
+/// otherwise just the first of them and how many there are
+fn print_solutions(result: Result<Solutions, NoSolution>, list: bool) {
+    let solutions = match result {
+        Ok(s) => s,
+        Err(e) => return print_solution(Err(e)),
+    };
+    let shown = if list { solutions.found.len() } else { 1 };
+    for moves in solutions.found.iter().take(shown) {
+        println!("{moves}");
+    }
+    let n = solutions.found.len();
+    let length = solutions.length;
+    match solutions.stopped {
+        Some(limit) => {
+            println!("gave up... {limit}. found {n} distinct solutions of {length} moves")
+        }
+        None if solutions.capped => println!("stopped at {n} distinct solutions of {length} moves"),
+        None => println!("{n} distinct optimal solutions of {length} moves"),
+    }
+}
+
+fn process_line(
+    puzzle: &mut puzzle::Puzzle,
+    session: &mut Session,
+    line: &str,
+) -> Result<(), Error> {
     let arr = line
         .split(' ')
         .filter_map(|s| match s.trim() {
@@ -123,13 +680,20 @@ fn process_line(puzzle: &mut puzzle::Puzzle, line: &str) -> Result<(), Error> {
         })
         .collect::<Vec<&str>>();
     arr.first().map_or(Ok(()), |command| {
-        process_command(puzzle, command, &arr.as_slice()[1..])
+        process_command(puzzle, session, command, &arr.as_slice()[1..])
     })
 }
 
 fn main() -> rustyline::Result<()> {
     let histfile = std::env::var("WATER_HISTFILE").unwrap_or_else(|_| "history.txt".to_owned());
     let mut puzzle = puzzle::Puzzle::new(12);
+    // Ctrl-C at the prompt is read by rustyline. The handler
+    // only sees the ones sent while a command is running.
+    let mut session = Session::default();
+    let handler = session.cancel.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler.cancel()) {
+        eprintln!("could not set Ctrl-C handler: {e}");
+    }
     let mut rl = Editor::<()>::new()?;
     if rl.load_history(&histfile).is_err() {
         println!("No previous history.");
@@ -139,7 +703,7 @@ fn main() -> rustyline::Result<()> {
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                if let Err(e) = process_line(&mut puzzle, &line) {
+                if let Err(e) = process_line(&mut puzzle, &mut session, &line) {
                     eprintln!("{e}");
                 }
             }
@@ -164,17 +728,23 @@ fn parse_int(i: Option<&&str>) -> Option<usize> {
     i.and_then(|s| s.parse::<usize>().ok())
 }
 
-fn set_tube<F>(size: usize, args: &[&str], usage: repl::Usage, mut cb: F) -> Result<(), Error>
+fn set_tube<F>(
+    size: usize,
+    capacity: usize,
+    args: &[&str],
+    usage: repl::Usage,
+    mut cb: F,
+) -> Result<(), Error>
 where
     F: FnMut(usize, usize),
 {
     match (parse_int(args.first()), parse_int(args.get(1))) {
-        (Some(tube), Some(idx)) if tube < size && idx < 4 => {
+        (Some(tube), Some(idx)) if tube < size && idx < capacity => {
             cb(tube, idx);
             Ok(())
         }
         (Some(tube), _) if tube >= size => Err(Error::InvalidTube(tube, size)),
-        (_, Some(idx)) if idx >= 4 => Err(Error::InvalidIndex),
+        (_, Some(idx)) if idx >= capacity => Err(Error::InvalidIndex(capacity)),
         (_, _) => Err(Error::Usage(usage)),
     }
 }
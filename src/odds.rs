@@ -0,0 +1,274 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::puzzle::{Invalid, Puzzle};
+use crate::solve::{dfs_puzzle, Budget, Cancel, MoveOrder, NoSolution, Search};
+use crate::state::State;
+use crate::water::Water;
+
+/// How often a move leads to a solution, over every way of filling
+/// in the unknown cells that [`move_odds`] tried
+pub struct MoveOdds {
+    pub played: (u8, u8),
+    /// Completions that can still be solved after this move
+    pub wins: usize,
+    /// Completions the search gave up on
+    pub undecided: usize,
+}
+
+pub struct Odds {
+    /// Completions tried
+    pub completions: usize,
+    /// Every possible completion was tried, not just a sample
+    pub exhaustive: bool,
+    pub moves: Vec<MoveOdds>,
+}
+
+impl MoveOdds {
+    pub fn probability(&self, completions: usize) -> f64 {
+        if completions == 0 {
+            0.0
+        } else {
+            self.wins as f64 / completions as f64
+        }
+    }
+}
+
+/// Estimates, for each move that can be made now, the chance that
+/// the puzzle can still be solved after it.
+///
+/// The unknown cells are filled in with whatever each colour is
+/// short of a full tube, plus whole tubes of colours not seen yet.
+/// If there are no more than `samples` distinct ways of doing that
+/// they are all tried, otherwise `samples` of them are picked at
+/// random. Each search is cut off after `nodes` expanded nodes.
+pub fn move_odds(
+    p: &Puzzle,
+    samples: usize,
+    nodes: usize,
+    cancel: Option<&Cancel>,
+) -> Result<Odds, Invalid> {
+    p.validate()?;
+    let (cells, colours) = hidden(p)?;
+    let mut moves: Vec<_> = p
+        .valid_moves()
+        .get()
+        .into_iter()
+        .map(|played| MoveOdds {
+            played,
+            wins: 0,
+            undecided: 0,
+        })
+        .collect();
+
+    let exhaustive = arrangements(&colours).is_some_and(|n| n <= samples as u128);
+    let mut rng = XorShift::seeded();
+    let mut fill = colours.clone();
+    let mut completions = 0;
+    while completions < samples && !cancel.is_some_and(Cancel::is_cancelled) {
+        if exhaustive {
+            if completions > 0 && !next_permutation(&mut fill) {
+                break;
+            }
+        } else {
+            rng.shuffle(&mut fill);
+        }
+        let mut complete = p.clone();
+        for (&(tube, idx), &w) in cells.iter().zip(&fill) {
+            complete.set_tube(tube, idx, State::Water(w));
+        }
+        for m in &mut moves {
+            let mut after = complete.clone();
+            // Hidden cells can lengthen the run on top so that it no
+            // longer fits, which makes the move a loss for this completion
+            if after.pour(m.played.0.into(), m.played.1.into()).is_err() {
+                continue;
+            }
+            let budget = Budget {
+                max_nodes: Some(nodes),
+                cancel: cancel.cloned(),
+                ..Budget::default()
+            };
+            match dfs_puzzle(&after, MoveOrder::default(), &mut Search::new(budget)) {
+                Ok(_) | Err(NoSolution::AlreadySolved) => m.wins += 1,
+                Err(NoSolution::OutOfBudget(..)) => m.undecided += 1,
+                Err(_) => {}
+            }
+        }
+        completions += 1;
+    }
+
+    Ok(Odds {
+        completions,
+        exhaustive: exhaustive && !cancel.is_some_and(Cancel::is_cancelled),
+        moves,
+    })
+}
+
+/// A tube and an index into it
+type Cell = (usize, usize);
+
+/// Where the unknown cells are, and the colours that have to go
+/// in them, sorted
+fn hidden(p: &Puzzle) -> Result<(Vec<Cell>, Vec<Water>), Invalid> {
+    let cells = p.unknown_cells();
+    let shortfall = p.shortfall();
+    let mut colours: Vec<_> = shortfall
+        .iter()
+        .flat_map(|(&w, &n)| std::iter::repeat_n(w, n))
+        .collect();
+    let short = colours.len();
+    let counts = p.colour_counts();
+    let capacity = p.capacity();
+    let unseen = Water::all().into_iter().filter(|w| !counts.contains_key(w));
+    for w in unseen.take(cells.len().saturating_sub(short) / capacity) {
+        colours.extend(std::iter::repeat_n(w, capacity));
+    }
+    if colours.len() != cells.len() {
+        return Err(Invalid::Unknowns {
+            short,
+            unknown: cells.len(),
+        });
+    }
+    colours.sort_unstable();
+    Ok((cells, colours))
+}
+
+/// Distinct orderings of `colours`, or `None` if there are too many to count
+fn arrangements(colours: &[Water]) -> Option<u128> {
+    let mut total: u128 = 1;
+    let mut run = 0;
+    for (i, w) in colours.iter().enumerate() {
+        run = if i > 0 && colours[i - 1] == *w {
+            run + 1
+        } else {
+            1
+        };
+        // Multiplying before dividing keeps every step whole
+        total = total.checked_mul(i as u128 + 1)? / run;
+    }
+    Some(total)
+}
+
+/// Rearranges `v` into the next ordering in lexicographic order,
+/// returning `false` once it was the last one
+fn next_permutation<T: Ord>(v: &mut [T]) -> bool {
+    let Some(i) = (1..v.len()).rev().find(|&i| v[i - 1] < v[i]) else {
+        return false;
+    };
+    let j = (i..v.len()).rev().find(|&j| v[i - 1] < v[j]).unwrap_or(i);
+    v.swap(i - 1, j);
+    v[i..].reverse();
+    true
+}
+
+/// Small, fast and good enough for picking samples
+struct XorShift(u64);
+
+impl XorShift {
+    fn seeded() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        // Zero is the one state it never leaves
+        Self(nanos | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Fisher-Yates
+    fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            v.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod odds_test {
+    use super::*;
+    use crate::state::State::{Unknown, Water};
+    use crate::water::{BLUE, GREEN, RED};
+
+    fn hidden_puzzle() -> Puzzle {
+        let mut p = Puzzle::new(5);
+        p.set_whole_tube(0, [Water(GREEN), Water(BLUE), Unknown, Unknown]);
+        p.set_whole_tube(1, [Water(BLUE), Water(BLUE), Water(RED), Water(GREEN)]);
+        p.set_whole_tube(2, [Water(BLUE), Water(RED), Unknown, Water(GREEN)]);
+        p
+    }
+
+    #[test]
+    fn test_arrangements() {
+        assert_eq!(arrangements(&[RED, RED, GREEN]), Some(3));
+        assert_eq!(arrangements(&[BLUE, GREEN, RED, RED]), Some(12));
+        assert_eq!(arrangements(&[RED; 200]), Some(1));
+
+        let mut v = [BLUE, RED, RED];
+        let mut seen = 1;
+        while next_permutation(&mut v) {
+            seen += 1;
+        }
+        assert_eq!(seen, 3);
+        assert_eq!(v, [RED, RED, BLUE]);
+    }
+
+    #[test]
+    fn test_move_odds() {
+        let p = hidden_puzzle();
+        let Ok(odds) = move_odds(&p, 100, 10_000, None) else {
+            panic!("puzzle is valid");
+        };
+        // One green and two reds are missing
+        assert!(odds.exhaustive);
+        assert_eq!(odds.completions, 3);
+        assert_eq!(odds.moves.len(), p.valid_moves().0.len());
+        for m in &odds.moves {
+            assert!(m.wins + m.undecided <= odds.completions);
+        }
+
+        let Ok(sampled) = move_odds(&p, 2, 10_000, None) else {
+            panic!("puzzle is valid");
+        };
+        assert!(!sampled.exhaustive);
+        assert_eq!(sampled.completions, 2);
+    }
+
+    #[test]
+    fn test_move_odds_run_grows() {
+        use crate::state::State::Empty;
+        let mut p = Puzzle::new(6);
+        p.set_whole_tube(0, [Empty, Water(RED), Unknown, Unknown]);
+        p.set_whole_tube(1, [Empty, Water(RED), Water(BLUE), Water(BLUE)]);
+        p.set_whole_tube(2, [Water(BLUE), Water(BLUE), Unknown, Unknown]);
+        p.set_whole_tube(3, [Empty, Empty, Unknown, Unknown]);
+        assert_eq!(p.validate(), Ok(()));
+        assert!(p.valid_moves().0.contains(&(0, 1)));
+
+        let Ok(odds) = move_odds(&p, 100, 1000, None) else {
+            panic!("puzzle is valid");
+        };
+        let Some(m) = odds.moves.iter().find(|m| m.played == (0, 1)) else {
+            panic!("(0, 1) is a valid move");
+        };
+        assert!(m.wins + m.undecided < odds.completions);
+    }
+
+    #[test]
+    fn test_move_odds_known() {
+        let mut p = hidden_puzzle();
+        p.set_whole_tube(0, [Water(GREEN), Water(BLUE), Water(RED), Water(RED)]);
+        p.set_tube(2, 2, Water(GREEN));
+        let Ok(odds) = move_odds(&p, 100, 10_000, None) else {
+            panic!("puzzle is valid");
+        };
+        assert!(odds.exhaustive);
+        assert_eq!(odds.completions, 1);
+        assert!(odds.moves.iter().any(|m| m.wins == 1));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::packed::Packed;
+use crate::puzzle::{Puzzle, ValidMoves};
+
+/// Shortens a chain of moves that solves `p`.
+///
+/// The chain is replayed, and the shortest way through the states it
+/// visits is picked, skipping back to a state seen before and jumping
+/// ahead wherever a single pour gets to a later state directly. Swapping
+/// pours between unrelated tubes can line up more of those jumps, so
+/// that is tried as well until nothing gets any shorter.
+///
+/// Chains that don't replay to a solved puzzle are returned as they are.
+pub fn optimise(p: &Puzzle, moves: &ValidMoves) -> ValidMoves {
+    let Some(mut best) = shortcut(p, &moves.0) else {
+        return ValidMoves(moves.0.clone());
+    };
+    loop {
+        let swapped = (1..best.len())
+            .filter(|&i| independent(best[i - 1], best[i]))
+            .find_map(|i| {
+                let mut chain = best.clone();
+                chain.swap(i - 1, i);
+                shortcut(p, &chain).filter(|c| c.len() < best.len())
+            });
+        match swapped {
+            Some(chain) => best = chain,
+            None => return ValidMoves(best),
+        }
+    }
+}
+
+/// Pours that don't share a tube can be played in either order
+pub const fn independent(a: (u8, u8), b: (u8, u8)) -> bool {
+    a.0 != b.0 && a.0 != b.1 && a.1 != b.0 && a.1 != b.1
+}
+
+/// One representative of every chain that only differs from `moves`
+/// by the order of [`independent`] pours: the smallest in
+/// lexicographic order. Two chains play out the same way exactly
+/// when their normal forms are equal.
+pub fn normal_form(moves: &[(u8, u8)]) -> Vec<(u8, u8)> {
+    let mut rest = moves.to_vec();
+    let mut normal = Vec::with_capacity(rest.len());
+    while !rest.is_empty() {
+        // Moves that could be played first are those independent
+        // of everything before them
+        let next = (0..rest.len())
+            .filter(|&i| rest[..i].iter().all(|&m| independent(m, rest[i])))
+            .min_by_key(|&i| rest[i])
+            .unwrap_or(0);
+        normal.push(rest.remove(next));
+    }
+    normal
+}
+
+type Step = (Option<(u8, u8)>, usize);
+
+/// The shortest solution through the states visited by `moves`,
+/// or `None` if they don't solve the puzzle
+fn shortcut(p: &Puzzle, moves: &[(u8, u8)]) -> Option<Vec<(u8, u8)>> {
+    let mut states = vec![p.clone()];
+    for m in moves {
+        let mut next = states[states.len() - 1].clone();
+        next.pour(m.0.into(), m.1.into()).ok()?;
+        states.push(next);
+    }
+    let end = states.iter().position(Puzzle::is_solved)?;
+    states.truncate(end + 1);
+
+    let mut seen = HashMap::<Packed, Vec<usize>>::new();
+    for (i, s) in states.iter().enumerate() {
+        seen.entry(Packed::from(s)).or_default().push(i);
+    }
+
+    // Fewest moves from each state to the end, and the next state on
+    // the way there. No move means skipping ahead to the same state.
+    let mut dist = vec![0; states.len()];
+    let mut step: Vec<Option<Step>> = vec![None; states.len()];
+    for i in (0..end).rev() {
+        dist[i] = dist[i + 1] + 1;
+        step[i] = Some((Some(moves[i]), i + 1));
+        // Coming back to the same state later on was a detour
+        for &j in &seen[&Packed::from(&states[i])] {
+            if j > i && dist[j] < dist[i] {
+                dist[i] = dist[j];
+                step[i] = Some((None, j));
+            }
+        }
+        for m in states[i].valid_moves().get() {
+            let mut next = states[i].clone();
+            next.pour(m.0.into(), m.1.into()).ok()?;
+            for &j in seen.get(&Packed::from(&next)).into_iter().flatten() {
+                if j > i && dist[j] + 1 < dist[i] {
+                    dist[i] = dist[j] + 1;
+                    step[i] = Some((Some(m), j));
+                }
+            }
+        }
+    }
+
+    let mut chain = vec![];
+    let mut i = 0;
+    while let Some((m, next)) = step[i] {
+        chain.extend(m);
+        i = next;
+    }
+    Some(chain)
+}
+
+#[cfg(test)]
+mod optimise_test {
+    use super::*;
+    use crate::fixtures::{solved, test_puzzle};
+    use crate::solve::{dfs_puzzle, MoveOrder, Search};
+    use crate::state::State::Water;
+    use crate::water::{BLUE, RED};
+
+    fn solves(p: &Puzzle, moves: &ValidMoves) -> bool {
+        let mut p = p.clone();
+        moves
+            .0
+            .iter()
+            .all(|m| p.pour(m.0.into(), m.1.into()).is_ok())
+            && p.is_solved()
+    }
+
+    #[test]
+    fn test_optimise_shortcut() {
+        let mut p = Puzzle::new(4);
+        p.set_whole_tube(0, [Water(BLUE), Water(RED), Water(RED), Water(RED)]);
+        p.set_whole_tube(1, [Water(RED), Water(BLUE), Water(BLUE), Water(BLUE)]);
+        // RED goes 1 -> 3 -> 0 where 1 -> 0 would do
+        let moves = ValidMoves(vec![(0, 2), (1, 3), (3, 0), (2, 1)]);
+        assert!(solves(&p, &moves));
+
+        let optimised = optimise(&p, &moves);
+        assert_eq!(optimised.0, vec![(0, 2), (1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_normal_form() {
+        let a = [(0, 1), (2, 3), (1, 2), (4, 0)];
+        let b = [(2, 3), (0, 1), (4, 0), (1, 2)];
+        assert_eq!(normal_form(&a), normal_form(&b));
+        assert_eq!(normal_form(&a), vec![(0, 1), (2, 3), (1, 2), (4, 0)]);
+        assert_ne!(
+            normal_form(&[(0, 1), (1, 2)]),
+            normal_form(&[(1, 2), (0, 1)])
+        );
+    }
+
+    #[test]
+    fn test_optimise_dfs() {
+        let p = test_puzzle();
+        let dfs = solved(dfs_puzzle(&p, MoveOrder::default(), &mut Search::default()));
+        let optimised = optimise(&p, &dfs);
+        assert!(solves(&p, &optimised));
+        assert!(optimised.0.len() <= dfs.0.len());
+    }
+
+    #[test]
+    fn test_optimise_invalid() {
+        let p = test_puzzle();
+        let moves = ValidMoves(vec![(3, 4)]);
+        assert_eq!(optimise(&p, &moves).0, moves.0);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::puzzle::Puzzle;
+use crate::state::State;
+use crate::tube::{Pour, Tube, MAX_CAPACITY};
+use crate::water::Water;
+
+/// Bits used by a single cell of a tube, enough for
+/// every colour of a palette besides empty and unknown
+const CELL_BITS: u32 = 6;
+const CELL_MASK: u64 = (1 << CELL_BITS) - 1;
+const UNKNOWN: u64 = CELL_MASK;
+/// Where the capacity goes, after the cells
+const CAPACITY_SHIFT: u32 = CELL_BITS * MAX_CAPACITY as u32;
+const CAPACITY_MASK: u64 = 0xf;
+/// The pour rule goes after the capacity. Like the capacity it is the
+/// same in every tube, so unpacking a tube needs nothing else.
+const POUR_SHIFT: u32 = CAPACITY_SHIFT + 4;
+const POUR_MASK: u64 = 0x3;
+const STICKY: u64 = 1 << 63;
+
+/// A puzzle packed into one word per tube, six bits per cell.
+///
+/// This is what the solvers keep in their frontiers and visited
+/// sets. A `Puzzle` is only unpacked to expand a node.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Packed(Box<[u64]>);
+
+impl Packed {
+    /// The same puzzle with its tubes sorted.
+    ///
+    /// Swapping the contents of two tubes doesn't change which moves
+    /// are needed to solve a puzzle (only which indices they use), so
+    /// every permutation of the same tubes shares one canonical form.
+    /// Identical tubes, such as the empty ones, become interchangeable.
+    /// Each tube is a single word, so only the words need sorting.
+    pub fn canonical(&self) -> Self {
+        let mut tubes = self.0.clone();
+        tubes.sort_unstable();
+        Self(tubes)
+    }
+
+    /// Memory taken up by this puzzle, including the heap
+    pub const fn bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + std::mem::size_of_val(&*self.0)
+    }
+}
+
+impl From<&Puzzle> for Packed {
+    fn from(p: &Puzzle) -> Self {
+        Self(p.tubes().iter().map(|t| pack_tube(*t)).collect())
+    }
+}
+
+impl From<&Packed> for Puzzle {
+    fn from(p: &Packed) -> Self {
+        Self::from(p.0.iter().map(|t| unpack_tube(*t)).collect::<Vec<_>>())
+    }
+}
+
+const fn pack_cell(s: State) -> u64 {
+    match s {
+        State::Empty => 0,
+        State::Water(w) => w.index() as u64 + 1,
+        State::Unknown => UNKNOWN,
+    }
+}
+
+const fn unpack_cell(c: u64) -> State {
+    match c {
+        0 => State::Empty,
+        UNKNOWN => State::Unknown,
+        c => State::Water(Water::from_index(c as usize - 1)),
+    }
+}
+
+fn pack_tube(t: Tube) -> u64 {
+    let cells = t.cells().iter().enumerate().fold(0, |acc, (i, s)| {
+        acc | pack_cell(*s) << (i as u32 * CELL_BITS)
+    }) | (t.capacity() as u64) << CAPACITY_SHIFT
+        | (t.pour_rule() as u64) << POUR_SHIFT;
+    if t.is_sticky() {
+        cells | STICKY
+    } else {
+        cells
+    }
+}
+
+fn unpack_tube(w: u64) -> Tube {
+    let mut t = Tube::with_capacity(((w >> CAPACITY_SHIFT) & CAPACITY_MASK) as usize);
+    for i in 0..t.capacity() {
+        t.set(i, unpack_cell((w >> (i as u32 * CELL_BITS)) & CELL_MASK));
+    }
+    t.set_sticky(w & STICKY != 0);
+    t.set_pour_rule(Pour::ALL[((w >> POUR_SHIFT) & POUR_MASK) as usize]);
+    t
+}
+
+#[cfg(test)]
+mod packed_test {
+    use super::*;
+    use crate::state::State::{Empty, Unknown, Water};
+    use crate::water::{BLUE, RED, YELLOW};
+
+    #[test]
+    fn test_round_trip() {
+        let mut p = Puzzle::new(4);
+        p.set_whole_tube(0, [Unknown, Water(RED), Water(BLUE), Water(YELLOW)]);
+        p.set_whole_tube(1, [Empty, Water(YELLOW), Water(RED), Water(BLUE)]);
+        p.set_sticky(1, true);
+
+        assert_eq!(Puzzle::from(&Packed::from(&p)), p);
+
+        let mut big = Puzzle::with_capacity(3, 8);
+        big.set_tube(0, 7, Water(YELLOW));
+        big.set_tube(0, 6, Water(crate::water::Water::from_index(61)));
+        big.set_sticky(0, true);
+        big.set_pour_rule(Pour::Overflow);
+        assert_eq!(Puzzle::from(&Packed::from(&big)), big);
+    }
+
+    #[test]
+    fn test_canonical() {
+        let mut p = Puzzle::new(4);
+        p.set_whole_tube(0, [Empty, Water(RED), Water(BLUE), Water(RED)]);
+        p.set_whole_tube(1, [Empty, Water(BLUE), Water(RED), Water(BLUE)]);
+        let mut q = Puzzle::new(4);
+        q.set_whole_tube(0, [Empty, Water(BLUE), Water(RED), Water(BLUE)]);
+        q.set_whole_tube(1, [Empty, Water(RED), Water(BLUE), Water(RED)]);
+
+        assert_ne!(Packed::from(&p), Packed::from(&q));
+        assert_eq!(Packed::from(&p).canonical(), Packed::from(&q).canonical());
+
+        // Words sort in another order than tubes, but hold the same ones
+        let unpacked = Puzzle::from(&Packed::from(&p).canonical());
+        assert_eq!(unpacked.canonical(), p.canonical());
+        assert_eq!(
+            Packed::from(&p.canonical()).canonical(),
+            Packed::from(&p).canonical()
+        );
+    }
+}
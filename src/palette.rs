@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::water::Water;
+
+/// Most colours a palette can have. Packed cells are six bits,
+/// and two of their values are taken by empty and unknown.
+pub const MAX_COLOURS: usize = 62;
+
+/// A colour as defined in a palette file
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Colour {
+    /// What the colour is saved as, and matched exactly when parsing
+    pub name: String,
+    /// Shorter ways of typing it
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub rgb: [u8; 3],
+}
+
+/// The colours puzzles can use. A [`Water`] is an index into it.
+///
+/// A palette file is a JSON list of colours, like
+/// `[{"name": "Teal", "aliases": ["t"], "rgb": [0, 128, 128]}]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette(Vec<Colour>);
+
+impl Palette {
+    /// The colours of the original game
+    pub fn builtin() -> Self {
+        let colour = |name: &str, aliases: &[&str], rgb| Colour {
+            name: name.to_owned(),
+            aliases: aliases.iter().map(|&a| a.to_owned()).collect(),
+            rgb,
+        };
+        Self(vec![
+            colour(
+                "Ash",
+                &["a", "ash", "G", "grey", "gray", "gr"],
+                [99, 100, 101],
+            ),
+            colour("Blue", &["b", "bl", "blue"], [58, 46, 195]),
+            colour("Brown", &["B", "br", "brown"], [126, 74, 7]),
+            colour("Cyan", &["c", "cy", "cyan"], [84, 163, 228]),
+            colour("Green", &["g", "green"], [17, 101, 51]),
+            colour("Lime", &["l", "lime"], [98, 214, 124]),
+            colour("Olive", &["O", "ol", "olive"], [120, 150, 15]),
+            colour("Orange", &["o", "or"], [232, 140, 66]),
+            colour("Pink", &["p", "pi", "pink"], [234, 94, 123]),
+            colour("Purple", &["P", "pu", "purple"], [113, 43, 147]),
+            colour("Red", &["r", "red"], [197, 42, 35]),
+            colour("Yellow", &["y", "yellow"], [241, 217, 87]),
+        ])
+    }
+
+    /// Reads a palette file, checking that every name and alias
+    /// picks out a single colour
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let colours: Vec<Colour> =
+            serde_json::from_str(json).map_err(|e| format!("invalid palette: {e}"))?;
+        if colours.is_empty() || colours.len() > MAX_COLOURS {
+            return Err(format!(
+                "a palette has {} colours, it must have between 1 and {MAX_COLOURS}",
+                colours.len()
+            ));
+        }
+        let mut seen = HashSet::new();
+        for c in &colours {
+            for key in std::iter::once(&c.name).chain(&c.aliases) {
+                if key.is_empty() {
+                    return Err(format!("{} has an empty name or alias", c.name));
+                }
+                if !seen.insert(key) {
+                    return Err(format!("{key} names more than one colour"));
+                }
+            }
+        }
+        Ok(Self(colours))
+    }
+
+    /// The colour named or aliased `s`
+    pub fn find(&self, s: &str) -> Option<Water> {
+        self.0
+            .iter()
+            .position(|c| c.name == s || c.aliases.iter().any(|a| a == s))
+            .map(Water::from_index)
+    }
+
+    pub fn get(&self, w: Water) -> Option<&Colour> {
+        self.0.get(w.index())
+    }
+
+    pub fn colours(&self) -> impl Iterator<Item = (Water, &Colour)> {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (Water::from_index(i), c))
+    }
+}
+
+static CURRENT: LazyLock<RwLock<Arc<Palette>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(Palette::builtin())));
+
+/// The palette colours are parsed, displayed and saved with
+pub fn current() -> Arc<Palette> {
+    CURRENT
+        .read()
+        .map_or_else(|e| e.into_inner().clone(), |p| p.clone())
+}
+
+pub fn set(palette: Palette) {
+    let mut current = CURRENT.write().unwrap_or_else(|e| e.into_inner());
+    *current = Arc::new(palette);
+}
+
+#[cfg(test)]
+mod palette_test {
+    use super::*;
+    use crate::water::{ASH, RED, YELLOW};
+
+    #[test]
+    fn test_builtin() {
+        let p = Palette::builtin();
+        assert_eq!(p.colours().count(), 12);
+        assert_eq!(p.find("r"), Some(RED));
+        assert_eq!(p.find("Red"), Some(RED));
+        assert_eq!(p.find("gray"), Some(ASH));
+        assert_eq!(p.find("teal"), None);
+        assert_eq!(p.get(YELLOW).map(|c| c.name.as_str()), Some("Yellow"));
+    }
+
+    #[test]
+    fn test_from_json() {
+        let p = Palette::from_json(
+            r#"[{"name": "Teal", "aliases": ["t"], "rgb": [0, 128, 128]},
+                {"name": "Red", "rgb": [200, 0, 0]}]"#,
+        )
+        .unwrap();
+        assert_eq!(p.find("t"), Some(Water::from_index(0)));
+        assert_eq!(p.find("Red"), Some(Water::from_index(1)));
+        assert_eq!(p.find("r"), None);
+
+        assert!(Palette::from_json("[]").is_err());
+        assert!(Palette::from_json(
+            r#"[{"name": "Teal", "rgb": [0, 128, 128]},
+                {"name": "Turquoise", "aliases": ["Teal"], "rgb": [0, 200, 200]}]"#
+        )
+        .is_err());
+    }
+}
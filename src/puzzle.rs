@@ -1,9 +1,96 @@
+use std::collections::BTreeMap;
 use std::hash::Hash;
 
 use crate::state::State;
+use crate::tube::{Pour, Tube, DEFAULT_CAPACITY};
+use crate::water::Water;
 
 #[derive(Default, Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
-pub struct Puzzle(Vec<crate::tube::Tube>);
+#[serde(try_from = "Loaded", into = "Saved")]
+pub struct Puzzle(Vec<Tube>);
+
+/// How a puzzle is saved
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Saved {
+    capacity: usize,
+    #[serde(default)]
+    pour: Pour,
+    tubes: Vec<Tube>,
+}
+
+/// What a puzzle can be loaded from. Puzzles saved before tubes
+/// could hold anything but four cells are just the list of tubes.
+enum Loaded {
+    Saved(Saved),
+    Legacy(Vec<Tube>),
+}
+
+/// Picks the format by the shape of the JSON, rather than trying
+/// each in turn, so errors say what is wrong within the format
+impl<'de> serde::Deserialize<'de> for Loaded {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+        use serde::Deserialize;
+
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Loaded;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a puzzle, or a list of tubes")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<Loaded, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(Loaded::Legacy)
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Loaded, A::Error> {
+                Saved::deserialize(MapAccessDeserializer::new(map)).map(Loaded::Saved)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl TryFrom<Loaded> for Puzzle {
+    type Error = String;
+
+    fn try_from(loaded: Loaded) -> Result<Self, Self::Error> {
+        let Saved {
+            capacity,
+            pour,
+            tubes,
+        } = match loaded {
+            Loaded::Saved(saved) => saved,
+            Loaded::Legacy(tubes) => Saved {
+                capacity: DEFAULT_CAPACITY,
+                pour: Pour::Whole,
+                tubes,
+            },
+        };
+        if let Some(i) = tubes.iter().position(|t| t.capacity() != capacity) {
+            return Err(format!(
+                "tube {i} holds {} cells, but the puzzle's tubes hold {capacity}",
+                tubes[i].capacity()
+            ));
+        }
+        let mut p = Self(tubes);
+        p.set_pour_rule(pour);
+        Ok(p)
+    }
+}
+
+impl From<Puzzle> for Saved {
+    fn from(p: Puzzle) -> Self {
+        Self {
+            capacity: p.capacity(),
+            pour: p.pour_rule(),
+            tubes: p.0,
+        }
+    }
+}
 
 impl Hash for Puzzle {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
@@ -15,16 +102,16 @@ impl Hash for Puzzle {
 
 impl std::fmt::Display for Puzzle {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let size = self.0.len();
-        let mid = if size % 2 == 0 {
-            size / 2
-        } else {
-            size / 2 + 1
-        };
-        self.print_row(f, 0, mid)?;
-        writeln!(f, "-------------------------")?;
-        self.print_row(f, mid, size)?;
-        Ok(())
+        self.print(f, None)
+    }
+}
+
+/// A puzzle displayed with the tubes of a move picked out
+pub struct Highlight<'a>(&'a Puzzle, (u8, u8));
+
+impl std::fmt::Display for Highlight<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.print(f, Some(self.1))
     }
 }
 
@@ -53,6 +140,51 @@ impl std::fmt::Display for ValidMoves {
     }
 }
 
+impl From<Vec<Tube>> for Puzzle {
+    fn from(tubes: Vec<Tube>) -> Self {
+        Self(tubes)
+    }
+}
+
+/// Something about a puzzle that rules out ever solving it
+#[derive(Debug, PartialEq, Eq)]
+pub enum Invalid {
+    /// There's no way to fill whole tubes, which hold
+    /// the last number of cells, with this much of a colour
+    ColourCount(Water, usize, usize),
+    /// The unknown cells can't make every colour fill whole tubes
+    Unknowns { short: usize, unknown: usize },
+    /// Something sits on top of an empty cell at (tube, index)
+    Floating(usize, usize),
+    /// Every cell is taken, so nothing can be poured
+    NoSpace,
+    /// A sticky tube holds something other than a single colour,
+    /// so it can never be filled to unlock it
+    Locked(usize),
+}
+
+impl std::fmt::Display for Invalid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ColourCount(w, n, capacity) => {
+                write!(f, "there are {n} {w:?}, which is not a multiple of {capacity}")
+            }
+            Self::Unknowns { short, unknown } => write!(
+                f,
+                "{unknown} unknown cells cannot make up the {short} missing to fill every colour's tubes"
+            ),
+            Self::Floating(tube, idx) => {
+                write!(f, "tube {tube} has something above the empty cell {idx}")
+            }
+            Self::NoSpace => write!(f, "every tube is full"),
+            Self::Locked(tube) => write!(
+                f,
+                "tube {tube} is sticky and holds more than one colour, so it can never be unlocked"
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct InvalidMove {
     pub from: usize,
@@ -61,21 +193,125 @@ pub struct InvalidMove {
 
 impl Puzzle {
     pub fn new(size: usize) -> Self {
-        let mut tubes = vec![crate::tube::Tube::default(); size - 2];
+        Self::with_capacity(size, DEFAULT_CAPACITY)
+    }
+
+    /// A puzzle of `size` tubes that each hold `capacity` cells
+    pub fn with_capacity(size: usize, capacity: usize) -> Self {
+        let mut tubes = vec![Tube::unknown(capacity); size - 2];
         // The other two tubes will always be empty
-        tubes.push(crate::tube::Tube::empty());
-        tubes.push(crate::tube::Tube::empty());
+        tubes.push(Tube::with_capacity(capacity));
+        tubes.push(Tube::with_capacity(capacity));
         Self(tubes)
     }
 
+    /// Cells each tube holds
+    pub fn capacity(&self) -> usize {
+        self.0.first().map_or(DEFAULT_CAPACITY, |t| t.capacity())
+    }
+
+    /// What happens to whatever doesn't fit when pouring
+    pub fn pour_rule(&self) -> Pour {
+        self.0.first().map_or(Pour::Whole, |t| t.pour_rule())
+    }
+
+    /// Adds an empty tube, as the game lets you do when stuck
+    pub fn add_tube(&mut self) {
+        let mut tube = Tube::with_capacity(self.capacity());
+        tube.set_pour_rule(self.pour_rule());
+        self.0.push(tube);
+    }
+
+    pub fn set_pour_rule(&mut self, pour: Pour) {
+        for t in &mut self.0 {
+            t.set_pour_rule(pour);
+        }
+    }
+
+    /// The same puzzle with its tubes sorted by content, to check
+    /// [`crate::packed::Packed::canonical`] against
+    #[cfg(test)]
+    pub fn canonical(&self) -> Self {
+        let mut tubes = self.0.clone();
+        tubes.sort_unstable();
+        Self(tubes)
+    }
+
+    /// How many cells of each colour there are
+    pub fn colour_counts(&self) -> BTreeMap<Water, usize> {
+        let mut counts = BTreeMap::new();
+        for t in &self.0 {
+            for &s in t.cells() {
+                if let State::Water(w) = s {
+                    *counts.entry(w).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Where the unknown cells are, as a tube and an index into it
+    pub fn unknown_cells(&self) -> Vec<(usize, usize)> {
+        self.0
+            .iter()
+            .enumerate()
+            .flat_map(|(tube, t)| (0..t.capacity()).map(move |idx| (tube, idx, t.get(idx))))
+            .filter(|c| c.2 == State::Unknown)
+            .map(|(tube, idx, _)| (tube, idx))
+            .collect()
+    }
+
+    /// How many more cells of each colour seen so far it takes
+    /// to fill whole tubes with it. Colours that already do are left out.
+    pub fn shortfall(&self) -> BTreeMap<Water, usize> {
+        let capacity = self.capacity();
+        self.colour_counts()
+            .into_iter()
+            .map(|(w, n)| (w, (capacity - n % capacity) % capacity))
+            .filter(|(_, n)| *n > 0)
+            .collect()
+    }
+
+    /// Looks for problems that make the puzzle unsolvable without
+    /// needing to search, such as a mistake entering it
+    pub fn validate(&self) -> Result<(), Invalid> {
+        let capacity = self.capacity();
+        for (tube, t) in self.0.iter().enumerate() {
+            for idx in 0..capacity - 1 {
+                if t.get(idx) != State::Empty && t.get(idx + 1) == State::Empty {
+                    return Err(Invalid::Floating(tube, idx + 1));
+                }
+            }
+            if t.is_sticky() && (t.num_runs() > 1 || t.cells().contains(&State::Unknown)) {
+                return Err(Invalid::Locked(tube));
+            }
+        }
+        let unknown = self.unknown_cells().len();
+        if unknown == 0 {
+            let counts = self.colour_counts();
+            if let Some((w, n)) = counts.iter().find(|(_, n)| !n.is_multiple_of(capacity)) {
+                return Err(Invalid::ColourCount(*w, *n, capacity));
+            }
+        } else {
+            // Whatever's left after topping up each colour has to
+            // be whole tubes of colours not seen yet
+            let short = self.shortfall().values().sum();
+            if unknown < short || !(unknown - short).is_multiple_of(capacity) {
+                return Err(Invalid::Unknowns { short, unknown });
+            }
+        }
+        if self.0.iter().all(|t| t.num_free() == 0) {
+            return Err(Invalid::NoSpace);
+        }
+        Ok(())
+    }
+
     pub fn has_unknown(&self) -> bool {
         self.0.iter().any(|t| t.top() == State::Unknown)
     }
 
     pub fn is_solved(&self) -> bool {
-        self.0
-            .iter()
-            .all(|t| t.num_to_pour() == 4 || t.num_free() == 4)
+        self.0.iter().all(|t| t.is_complete() || t.is_empty())
     }
 
     pub fn valid_moves(&self) -> ValidMoves {
@@ -85,7 +321,7 @@ impl Puzzle {
                 if i == j {
                     continue;
                 }
-                if self.0[i].can_pour_to(self.0[j]) && self.0[i].num_to_pour() != 4 {
+                if self.0[i].can_pour_to(self.0[j]) && !self.0[i].is_complete() {
                     valid.push((i as u8, j as u8));
                 }
             }
@@ -106,14 +342,22 @@ impl Puzzle {
     }
 
     #[allow(dead_code)]
-    pub fn set_whole_tube(&mut self, tube: usize, state: [crate::state::State; 4]) {
-        self.0[tube].set_tube(state);
+    pub fn set_whole_tube<const N: usize>(&mut self, tube: usize, state: [State; N]) {
+        self.0[tube].set_tube(&state);
+    }
+
+    pub fn set_sticky(&mut self, tube: usize, sticky: bool) {
+        self.0[tube].set_sticky(sticky);
     }
 
     pub fn set_tube(&mut self, tube: usize, idx: usize, state: crate::state::State) {
         self.0[tube].set(idx, state);
     }
 
+    pub fn tubes(&self) -> &[Tube] {
+        &self.0
+    }
+
     pub fn size(&self) -> usize {
         self.0.len()
     }
@@ -122,23 +366,61 @@ impl Puzzle {
         self.0 = p.0;
     }
 
+    pub const fn highlight(&self, m: (u8, u8)) -> Highlight<'_> {
+        Highlight(self, m)
+    }
+
+    fn print(&self, f: &mut std::fmt::Formatter<'_>, m: Option<(u8, u8)>) -> std::fmt::Result {
+        let size = self.0.len();
+        let mid = if size.is_multiple_of(2) {
+            size / 2
+        } else {
+            size / 2 + 1
+        };
+        self.print_row(f, 0, mid, m)?;
+        writeln!(f, "-------------------------")?;
+        self.print_row(f, mid, size, m)?;
+        Ok(())
+    }
+
     fn print_row(
         &self,
         f: &mut std::fmt::Formatter<'_>,
         start: usize,
         end: usize,
+        m: Option<(u8, u8)>,
     ) -> std::fmt::Result {
+        use ansi_term::Colour::{Black, Green, Yellow};
+        // Pour from yellow into green
+        let label = |tube: usize| {
+            let text = format!("{tube:3}");
+            match m {
+                Some((from, _)) if usize::from(from) == tube => Black.on(Yellow).paint(text),
+                Some((_, to)) if usize::from(to) == tube => Black.on(Green).paint(text),
+                _ => text.into(),
+            }
+        };
         write!(f, "{:2}", " ")?;
         for tube in start..end - 1 {
-            write!(f, "{tube:3}   ")?;
+            write!(f, "{}   ", label(tube))?;
         }
-        writeln!(f, "{:3}", end - 1)?;
-        for row in 0..4 {
+        writeln!(f, "{}", label(end - 1))?;
+        // Sticky tubes get square walls
+        let cell = |tube: usize, row: usize| {
+            let t = self.0[tube];
+            let (l, r) = if t.is_sticky() {
+                ('[', ']')
+            } else {
+                ('|', '|')
+            };
+            format!("{l}{}{r}", t.get(row))
+        };
+        for row in 0..self.capacity() {
             write!(f, "{row} ")?;
             for tube in start..end - 1 {
-                write!(f, "|{}| ", self.0[tube].get(row))?;
+                write!(f, "{} ", cell(tube, row))?;
             }
-            writeln!(f, "|{}|", self.0[end - 1].get(row))?;
+            writeln!(f, "{}", cell(end - 1, row))?;
         }
         Ok(())
     }
@@ -147,21 +429,189 @@ impl Puzzle {
 #[cfg(test)]
 mod puzzle_test {
     use super::*;
-    use crate::state::State::{Empty, Water};
-    use crate::water::Water::Red;
+    use crate::state::State::{Empty, Unknown, Water};
+    use crate::water::{BLUE, RED};
     #[test]
     fn test_is_solved() {
         let mut p = Puzzle::new(3);
         p.set_tube(0, 0, Empty);
         p.set_tube(0, 1, Empty);
-        p.set_tube(0, 2, Water(Red));
-        p.set_tube(0, 3, Water(Red));
+        p.set_tube(0, 2, Water(RED));
+        p.set_tube(0, 3, Water(RED));
         p.set_tube(1, 0, Empty);
         p.set_tube(1, 1, Empty);
-        p.set_tube(1, 2, Water(Red));
-        p.set_tube(1, 3, Water(Red));
+        p.set_tube(1, 2, Water(RED));
+        p.set_tube(1, 3, Water(RED));
         assert!(!p.is_solved());
         p.pour(0, 1).unwrap();
         assert!(p.is_solved());
     }
+
+    #[test]
+    fn test_validate() {
+        let mut p = Puzzle::new(4);
+        p.set_whole_tube(0, [Water(RED), Water(RED), Water(BLUE), Water(RED)]);
+        p.set_whole_tube(1, [Water(BLUE), Water(BLUE), Water(RED), Water(BLUE)]);
+        assert_eq!(p.validate(), Ok(()));
+        assert_eq!(p.colour_counts().get(&RED), Some(&4));
+
+        p.set_tube(1, 0, Water(RED));
+        assert_eq!(p.validate(), Err(Invalid::ColourCount(BLUE, 3, 4)));
+        p.set_tube(1, 0, Unknown);
+        assert_eq!(p.validate(), Ok(()));
+        p.set_tube(1, 1, Water(RED));
+        assert_eq!(
+            p.validate(),
+            Err(Invalid::Unknowns {
+                short: 5,
+                unknown: 1
+            })
+        );
+
+        p.set_whole_tube(1, [Water(BLUE), Empty, Water(RED), Water(BLUE)]);
+        assert_eq!(p.validate(), Err(Invalid::Floating(1, 1)));
+        assert_eq!(
+            Invalid::Floating(1, 1).to_string(),
+            "tube 1 has something above the empty cell 1"
+        );
+
+        p.set_whole_tube(1, [Water(BLUE), Water(BLUE), Water(RED), Water(BLUE)]);
+        p.set_sticky(1, true);
+        assert_eq!(p.validate(), Err(Invalid::Locked(1)));
+        p.set_whole_tube(1, [Empty, Water(BLUE), Water(BLUE), Water(BLUE)]);
+        p.set_whole_tube(0, [Water(RED), Water(BLUE), Water(RED), Water(RED)]);
+        p.set_whole_tube(2, [Empty, Empty, Empty, Water(RED)]);
+        assert_eq!(p.validate(), Ok(()));
+
+        let mut full = Puzzle::from(vec![Tube::empty(); 2]);
+        full.set_whole_tube(0, [Water(RED), Water(BLUE), Water(RED), Water(BLUE)]);
+        full.set_whole_tube(1, [Water(BLUE), Water(RED), Water(BLUE), Water(RED)]);
+        assert_eq!(full.validate(), Err(Invalid::NoSpace));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let p = Puzzle::new(4);
+        let plain = p.to_string();
+        let marked = p.highlight((1, 3)).to_string();
+        assert_ne!(plain, marked);
+        assert!(marked.contains(
+            &ansi_term::Colour::Black
+                .on(ansi_term::Colour::Yellow)
+                .paint("  1")
+                .to_string()
+        ));
+        assert!(marked.contains(
+            &ansi_term::Colour::Black
+                .on(ansi_term::Colour::Green)
+                .paint("  3")
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn test_sticky() {
+        let mut p = Puzzle::new(4);
+        p.set_whole_tube(0, [Water(RED), Water(BLUE), Water(BLUE), Water(BLUE)]);
+        p.set_whole_tube(1, [Empty, Water(RED), Water(RED), Water(RED)]);
+        let plain = p.to_string();
+        p.set_sticky(1, true);
+        assert_ne!(p.to_string(), plain);
+        assert!(p.to_string().contains(']'));
+
+        assert!(p.pour(1, 2).is_err());
+        assert_eq!(p.valid_moves().get(), vec![(0, 1), (0, 2), (0, 3)]);
+        p.pour(0, 1).unwrap();
+        assert!(p.tubes()[1].is_complete());
+        assert!(!p.tubes()[1].is_sticky());
+    }
+
+    #[test]
+    fn test_pour_rule() {
+        let mut p = Puzzle::new(4);
+        p.set_whole_tube(0, [Empty, Water(RED), Water(RED), Water(BLUE)]);
+        p.set_whole_tube(1, [Empty, Water(RED), Water(BLUE), Water(BLUE)]);
+        p.set_whole_tube(2, [Empty, Empty, Empty, Water(RED)]);
+        assert!(p.pour(0, 1).is_err());
+        let whole = p.valid_moves().get();
+
+        p.set_pour_rule(Pour::Overflow);
+        let overflow = p.valid_moves().get();
+        assert!(whole.iter().all(|m| overflow.contains(m)));
+        assert!(!whole.contains(&(0, 1)) && overflow.contains(&(0, 1)));
+
+        let json = serde_json::to_string(&p).unwrap();
+        assert!(json.contains(r#""pour":"Overflow""#));
+        let loaded = serde_json::from_str::<Puzzle>(&json).unwrap();
+        assert_eq!(loaded.pour_rule(), Pour::Overflow);
+        let old = json.replacen(r#""pour":"Overflow","#, "", 1);
+        assert_eq!(
+            serde_json::from_str::<Puzzle>(&old).unwrap().pour_rule(),
+            Pour::Whole
+        );
+
+        // One red fits, and the other stays behind
+        p.pour(0, 1).unwrap();
+        assert_eq!(
+            (p.tubes()[0].num_free(), p.tubes()[0].num_to_pour()),
+            (2, 1)
+        );
+        assert_eq!(
+            (p.tubes()[1].num_free(), p.tubes()[1].num_to_pour()),
+            (0, 2)
+        );
+
+        p.set_pour_rule(Pour::Single);
+        let json = serde_json::to_string(&p).unwrap();
+        assert!(json.contains(r#""pour":"Single""#));
+        assert_eq!(serde_json::from_str::<Puzzle>(&json).unwrap(), p);
+        p.pour(0, 2).unwrap();
+        assert_eq!(p.tubes()[2].num_to_pour(), 2);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut p = Puzzle::with_capacity(4, 3);
+        p.set_whole_tube(0, [Water(RED), Water(BLUE), Water(RED)]);
+        p.set_whole_tube(1, [Water(BLUE), Water(RED), Water(BLUE)]);
+        assert_eq!(p.capacity(), 3);
+        assert_eq!(p.validate(), Ok(()));
+        p.set_tube(1, 0, Water(RED));
+        assert_eq!(p.validate(), Err(Invalid::ColourCount(BLUE, 2, 3)));
+
+        let json = serde_json::to_string(&p).unwrap();
+        assert!(json.starts_with(r#"{"capacity":3,"#));
+        assert_eq!(serde_json::from_str::<Puzzle>(&json).unwrap(), p);
+
+        // Saved before there was a capacity
+        let legacy = serde_json::to_string(&Puzzle::new(3).0).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Puzzle>(&legacy).unwrap(),
+            Puzzle::new(3)
+        );
+
+        p.set_pour_rule(Pour::Single);
+        p.add_tube();
+        assert_eq!(p.size(), 5);
+        assert_eq!(p.tubes()[4].capacity(), 3);
+        assert_eq!(p.tubes()[4].pour_rule(), Pour::Single);
+        assert!(p.tubes()[4].is_empty());
+
+        let mixed = json.replacen(r#""capacity":3"#, r#""capacity":4"#, 1);
+        assert!(serde_json::from_str::<Puzzle>(&mixed).is_err());
+    }
+
+    #[test]
+    fn test_canonical() {
+        let mut p = Puzzle::new(4);
+        p.set_whole_tube(0, [Empty, Water(RED), Water(BLUE), Water(RED)]);
+        p.set_whole_tube(1, [Empty, Water(BLUE), Water(RED), Water(BLUE)]);
+        let mut q = Puzzle::new(4);
+        q.set_whole_tube(0, [Empty, Water(BLUE), Water(RED), Water(BLUE)]);
+        q.set_whole_tube(1, [Empty, Water(RED), Water(BLUE), Water(RED)]);
+
+        assert_ne!(p, q);
+        assert_eq!(p.canonical(), q.canonical());
+        assert_eq!(p.canonical().canonical(), p.canonical());
+    }
 }
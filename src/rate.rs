@@ -0,0 +1,182 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::packed::Packed;
+use crate::puzzle::{Puzzle, ValidMoves};
+use crate::solve::{Cancel, NoSolution};
+
+/// What makes a puzzle hard, found by exploring every state it can reach
+pub struct Rating {
+    /// Moves in the shortest solution, if there is one
+    pub optimal: Option<usize>,
+    /// Distinct states reachable, counting tubes in any order as one
+    pub states: usize,
+    /// States from which the puzzle can no longer be solved
+    pub dead: usize,
+    /// States with no moves at all that aren't solved
+    pub stuck: usize,
+    pub tubes: usize,
+    /// Tubes beyond those the colours fill when solved
+    pub spare: usize,
+    /// Moves available from the average state that has any
+    pub branching: f64,
+    /// Every reachable state was explored. If not, the
+    /// counts only cover the states that were.
+    pub complete: bool,
+}
+
+impl Rating {
+    /// A single number to compare puzzles by, higher is harder.
+    ///
+    /// Longer solutions through bigger state spaces are harder, more
+    /// so the easier it is to get stuck, and spare tubes make up for
+    /// a lot of that. `None` if the puzzle can't be solved.
+    pub fn score(&self) -> Option<f64> {
+        let optimal = self.optimal? as f64;
+        let dead = self.dead as f64 / self.states.max(1) as f64;
+        let size = (self.states as f64 + 1.0).log10();
+        Some(optimal * size * (1.0 + 2.0 * dead) / (self.spare as f64 + 1.0))
+    }
+}
+
+impl std::fmt::Display for Rating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.score() {
+            Some(score) => writeln!(f, "rating:           {score:.1}")?,
+            None if self.complete => writeln!(f, "rating:           unsolvable")?,
+            None => writeln!(f, "rating:           unknown, no solution found yet")?,
+        }
+        match self.optimal {
+            Some(n) => writeln!(f, "optimal moves:    {n}")?,
+            None => writeln!(f, "optimal moves:    -")?,
+        }
+        let partial = if self.complete { "" } else { " (partial)" };
+        writeln!(f, "states:           {}{partial}", self.states)?;
+        writeln!(f, "dead states:      {}{partial}", self.dead)?;
+        writeln!(f, "stuck states:     {}{partial}", self.stuck)?;
+        writeln!(f, "tubes:            {} ({} spare)", self.tubes, self.spare)?;
+        write!(f, "branching factor: {:.3}", self.branching)
+    }
+}
+
+/// Explores up to `max_states` states reachable from `p` to rate it
+pub fn rate(p: &Puzzle, max_states: usize, cancel: Option<&Cancel>) -> Result<Rating, NoSolution> {
+    p.validate().map_err(NoSolution::Invalid)?;
+    // Hidden anywhere, not just on top, as pouring uncovers them
+    if !p.unknown_cells().is_empty() {
+        return Err(NoSolution::HasUnknown(p.clone(), ValidMoves(vec![])));
+    }
+
+    let root = Packed::from(p).canonical();
+    let mut index = HashMap::from([(root.clone(), 0)]);
+    let mut states = vec![root];
+    let mut depths = vec![0];
+    // Edges are kept backwards, to find the states that lead to a solution
+    let mut parents: Vec<Vec<usize>> = vec![vec![]];
+    let mut solved = vec![];
+    let mut queue = VecDeque::from([0]);
+    let mut stuck = 0;
+    let mut moves = 0;
+    let mut expanded = 0;
+    let mut complete = true;
+
+    while let Some(i) = queue.pop_front() {
+        let puzzle = Puzzle::from(&states[i]);
+        if puzzle.is_solved() {
+            solved.push(i);
+            continue;
+        }
+        let children = puzzle.valid_moves().get();
+        if children.is_empty() {
+            stuck += 1;
+            continue;
+        }
+        expanded += 1;
+        moves += children.len();
+        for m in children {
+            let mut new_p = puzzle.clone();
+            new_p.pour(m.0.into(), m.1.into()).unwrap();
+            let key = Packed::from(&new_p).canonical();
+            if let Some(&j) = index.get(&key) {
+                parents[j].push(i);
+                continue;
+            }
+            if states.len() >= max_states || cancel.is_some_and(Cancel::is_cancelled) {
+                complete = false;
+                continue;
+            }
+            let j = states.len();
+            index.insert(key.clone(), j);
+            states.push(key);
+            depths.push(depths[i] + 1);
+            parents.push(vec![i]);
+            queue.push_back(j);
+        }
+    }
+
+    let mut alive = vec![false; states.len()];
+    let mut back: VecDeque<_> = solved.iter().copied().collect();
+    for &i in &solved {
+        alive[i] = true;
+    }
+    while let Some(i) = back.pop_front() {
+        for &j in &parents[i] {
+            if !alive[j] {
+                alive[j] = true;
+                back.push_back(j);
+            }
+        }
+    }
+
+    let colours = p.colour_counts().values().sum::<usize>() / p.capacity();
+    Ok(Rating {
+        optimal: solved.iter().map(|&i| depths[i]).min(),
+        states: states.len(),
+        dead: alive.iter().filter(|a| !**a).count(),
+        stuck,
+        tubes: p.size(),
+        spare: p.size() - colours,
+        branching: moves as f64 / expanded.max(1) as f64,
+        complete,
+    })
+}
+
+#[cfg(test)]
+mod rate_test {
+    use super::*;
+    use crate::fixtures::{solved, test_puzzle};
+    use crate::solve::{bfs_puzzle, Search};
+    use crate::state::State::Unknown;
+
+    #[test]
+    fn test_rate() {
+        let p = test_puzzle();
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        let Ok(rating) = rate(&p, 1_000_000, None) else {
+            panic!("puzzle should be rated");
+        };
+        assert!(rating.complete);
+        assert_eq!(rating.optimal, Some(optimal.0.len()));
+        assert!(rating.states > rating.dead);
+        assert!(rating.dead >= rating.stuck);
+        assert_eq!((rating.tubes, rating.spare), (5, 2));
+        assert!(rating.branching > 1.0);
+        assert!(rating.score().is_some_and(|s| s > 0.0));
+
+        let Ok(partial) = rate(&p, 10, None) else {
+            panic!("puzzle should be rated");
+        };
+        assert!(!partial.complete);
+        assert_eq!(partial.states, 10);
+    }
+
+    #[test]
+    fn test_rate_hidden() {
+        let mut p = test_puzzle();
+        p.set_tube(0, 2, Unknown);
+        assert!(!p.has_unknown());
+        assert!(matches!(
+            rate(&p, 1_000, None),
+            Err(NoSolution::HasUnknown(..))
+        ));
+    }
+}
@@ -4,8 +4,10 @@ pub enum Error {
     Usage(Usage),
     InvalidTube(usize, usize),
     InvalidPour(usize, usize),
-    InvalidIndex,
+    InvalidIndex(usize),
+    InvalidCapacity,
     UnrecognizedCommand(String),
+    UnknownStrategy(String),
     UnknownWaterColour(String),
 }
 
@@ -13,26 +15,43 @@ pub enum Usage {
     Init,
     Load,
     Save,
+    Solve,
+    Odds,
+    Rate,
     Pour,
     Unset,
     Empty,
     Set,
     Tube,
     QuickTube,
+    Sticky,
+    Rule,
+    AddTube,
+    Palette,
 }
 
 fn usage(u: &Usage, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.write_str("usage: ")?;
     match u {
-        Usage::Init => write!(f, "init <size>"),
+        Usage::Init => write!(f, "init <size> [<capacity>]"),
         Usage::Load => write!(f, "load <file>"),
         Usage::Save => write!(f, "save <file>"),
+        Usage::Solve => write!(
+            f,
+            "solve [<strategy> [<args>] [--extra [<n>]] | --all [<n>]] [--timeout <secs>] [--nodes <n>] [--depth <n>] [--stats] [--optimise]"
+        ),
+        Usage::Odds => write!(f, "odds [<samples>] [--nodes <n>]"),
+        Usage::Rate => write!(f, "rate [<file>|<dir>] [--states <n>]"),
         Usage::Pour => write!(f, "pour <tube> <tube>"),
         Usage::Unset => write!(f, "unset <tube> <idx>"),
         Usage::Empty => write!(f, "empty <tube> <idx>"),
         Usage::Set => write!(f, "set <tube> <idx> <colour>"),
         Usage::Tube => write!(f, "tube [<tube> <colours>]+"),
         Usage::QuickTube => write!(f, "tt [<idx>[<colour>]+]+"),
+        Usage::Sticky => write!(f, "sticky <tube>"),
+        Usage::Rule => write!(f, "rule [whole|overflow|single]"),
+        Usage::AddTube => write!(f, "addtube [<n>]"),
+        Usage::Palette => write!(f, "palette [<file>|default]"),
     }
 }
 
@@ -48,8 +67,16 @@ impl std::fmt::Display for Error {
                 size - 1
             ),
             Self::InvalidPour(a, b) => write!(f, "cannot pour from {a} to {b}"),
-            Self::InvalidIndex => write!(f, "index must be between 0 and 3"),
+            Self::InvalidIndex(capacity) => {
+                write!(f, "index must be between 0 and {}", capacity - 1)
+            }
+            Self::InvalidCapacity => write!(
+                f,
+                "capacity must be between 1 and {}",
+                crate::tube::MAX_CAPACITY
+            ),
             Self::UnrecognizedCommand(c) => write!(f, "Unrecognized command: {c}"),
+            Self::UnknownStrategy(s) => write!(f, "Unknown strategy: {s}. see `solvers`"),
             Self::UnknownWaterColour(c) => write!(f, "Unknown colour: {c}"),
         }
     }
@@ -1,9 +1,19 @@
-use crate::puzzle::{Puzzle, ValidMoves};
-use indextree_ng::{Arena, NodeId};
-use std::collections::{HashSet, VecDeque};
+use crate::optimise::{independent, normal_form};
+use crate::packed::Packed;
+use crate::puzzle::{Invalid, Puzzle, ValidMoves};
+use crate::state::State;
+use indextree::{Arena, NodeId};
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub struct PuzzleState {
-    pub puzzle: Puzzle,
+    pub puzzle: Packed,
     pub played_move: (u8, u8),
     depth: usize,
 }
@@ -12,24 +22,517 @@ pub enum NoSolution {
     AlreadySolved,
     CannotBeSolved(ValidMoves, usize),
     HasUnknown(Puzzle, ValidMoves),
+    /// Could be told without searching, see [`Puzzle::validate`]
+    Invalid(Invalid),
+    /// No pours uncover a hidden cell without getting stuck
+    NothingToReveal,
+    /// Gave up before finishing the search. Carries the chain of
+    /// moves that got closest to solving the puzzle.
+    OutOfBudget(ValidMoves, Limit),
 }
 
-pub fn dfs_puzzle(p: &Puzzle) -> Result<ValidMoves, NoSolution> {
-    if p.is_solved() {
-        return Err(NoSolution::AlreadySolved);
+/// Shared flag for stopping a running search from elsewhere,
+/// such as a Ctrl-C handler
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn cancel(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/// How much work a search may do before giving up
+#[derive(Clone, Default)]
+pub struct Budget {
+    pub timeout: Option<Duration>,
+    /// Most nodes expanded
+    pub max_nodes: Option<usize>,
+    /// Longest chain of moves explored
+    pub max_depth: Option<usize>,
+    pub cancel: Option<Cancel>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Timeout,
+    Nodes,
+    Depth,
+    Cancelled,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out"),
+            Self::Nodes => write!(f, "node limit reached"),
+            Self::Depth => write!(f, "depth limit reached"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl Budget {
+    /// Whether a search that started at `started` and has
+    /// expanded `expanded` nodes has to stop
+    fn check(&self, started: Instant, expanded: usize) -> Result<(), Limit> {
+        if self.cancel.as_ref().is_some_and(Cancel::is_cancelled) {
+            return Err(Limit::Cancelled);
+        }
+        if self.max_nodes.is_some_and(|n| expanded >= n) {
+            return Err(Limit::Nodes);
+        }
+        if self.timeout.is_some_and(|t| started.elapsed() >= t) {
+            return Err(Limit::Timeout);
+        }
+        Ok(())
+    }
+
+    /// Whether the children of a node `moves` moves deep are out of reach
+    fn beyond(&self, moves: usize) -> bool {
+        self.max_depth.is_some_and(|d| moves >= d)
+    }
+}
+
+/// What a search did, for comparing strategies
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub generated: usize,
+    pub expanded: usize,
+    /// Nodes dropped for repeating an earlier state
+    pub duplicates: usize,
+    /// Most states held in the visited set at once
+    pub peak_visited: usize,
+    /// Rough size of those states in memory, ignoring the set itself
+    pub visited_bytes: usize,
+    pub max_depth: usize,
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    /// The branching factor `b` of a uniform tree `max_depth` deep
+    /// with as many nodes as were generated,
+    /// i.e. `generated = b + b^2 + ... + b^max_depth`
+    pub fn branching_factor(&self) -> f64 {
+        let (n, d) = (self.generated as f64, self.max_depth as i32);
+        if d == 0 || n == 0.0 {
+            return 0.0;
+        }
+        let nodes = |b: f64| (1..=d).map(|i| b.powi(i)).sum::<f64>();
+        let (mut lo, mut hi) = (0.0, n.max(1.0));
+        for _ in 0..64 {
+            let mid = (lo + hi) / 2.0;
+            if nodes(mid) < n {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "time:             {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "generated:        {}", self.generated)?;
+        writeln!(f, "expanded:         {}", self.expanded)?;
+        writeln!(f, "duplicates:       {}", self.duplicates)?;
+        writeln!(
+            f,
+            "peak visited:     {} (~{} KiB)",
+            self.peak_visited,
+            self.visited_bytes / 1024
+        )?;
+        writeln!(f, "max depth:        {}", self.max_depth)?;
+        write!(f, "branching factor: {:.3}", self.branching_factor())
+    }
+}
+
+/// Snapshot of a running search, as handed to an observer
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Progress {
+    pub expanded: usize,
+    /// Nodes waiting to be expanded
+    pub frontier: usize,
+    pub visited: usize,
+    /// Most moves played to reach any node expanded so far
+    pub depth: usize,
+    pub elapsed: Duration,
+}
+
+/// A solution remembered by every state it passes through, so it
+/// can be picked up again from any of them, and whatever the search
+/// that found it learned about the states around it
+#[derive(Default)]
+pub struct Plan {
+    rest: HashMap<Packed, Vec<(u8, u8)>>,
+    explored: Option<Explored>,
+}
+
+impl Plan {
+    /// `None` unless `moves` solve `p`. Chains that stop early, like
+    /// those uncovering hidden cells, are nothing to follow.
+    pub fn new(p: &Puzzle, moves: &ValidMoves) -> Option<Self> {
+        let mut rest = HashMap::new();
+        let mut p = p.clone();
+        for (i, m) in moves.0.iter().enumerate() {
+            rest.insert(Packed::from(&p), moves.0[i..].to_vec());
+            p.pour(m.0.into(), m.1.into()).ok()?;
+        }
+        p.is_solved().then_some(Self {
+            rest,
+            explored: None,
+        })
+    }
+
+    /// Keeps the states the search for the solution reached
+    #[must_use]
+    pub fn explored(mut self, explored: Option<Explored>) -> Self {
+        self.explored = explored;
+        self
+    }
+
+    /// The rest of the solution from `p`, if it is on the way
+    pub fn rest(&self, p: &Puzzle) -> Option<ValidMoves> {
+        self.rest.get(&Packed::from(p)).cloned().map(ValidMoves)
+    }
+}
+
+/// The states a breadth-first search reached before it found an
+/// optimal solution, by the fewest moves from where it started.
+///
+/// A state `d` moves from a start that is `length` moves from solved
+/// is at least `length - d` moves from solved itself, wherever the
+/// search starts now. Every state fewer moves in is known,
+/// so the bound never drops by more than a move per pour, and
+/// [`heuristic`] stays consistent when raised to it.
+pub struct Explored {
+    length: usize,
+    depths: HashMap<Packed, usize>,
+}
+
+impl Explored {
+    /// `key` is canonical
+    fn bound(&self, key: &Packed) -> usize {
+        self.depths
+            .get(key)
+            .map_or(0, |&d| self.length.saturating_sub(d))
+    }
+}
+
+type Observer<'a> = Box<dyn FnMut(&Progress) + 'a>;
+
+/// Keeps track of a single run of a solver against its [`Budget`],
+/// and reports on how it is going to an optional observer
+pub struct Search<'a> {
+    pub budget: Budget,
+    observer: Option<(Observer<'a>, Duration)>,
+    plan: Option<&'a Plan>,
+    started: Instant,
+    reported: Instant,
+    expanded: usize,
+    generated: usize,
+    duplicates: usize,
+    peak_visited: usize,
+    entry_bytes: usize,
+    depth: usize,
+    depth_limited: bool,
+    best: Option<(usize, ValidMoves)>,
+    explored: Option<Explored>,
+}
+
+impl Default for Search<'_> {
+    fn default() -> Self {
+        Self::new(Budget::default())
+    }
+}
+
+impl<'a> Search<'a> {
+    pub fn new(budget: Budget) -> Self {
+        let now = Instant::now();
+        Self {
+            budget,
+            observer: None,
+            plan: None,
+            started: now,
+            reported: now,
+            expanded: 0,
+            generated: 0,
+            duplicates: 0,
+            peak_visited: 0,
+            entry_bytes: 0,
+            depth: 0,
+            depth_limited: false,
+            best: None,
+            explored: None,
+        }
+    }
+
+    /// Calls `observer` with the progress of the search
+    /// at most once every `interval`
+    pub fn observe(mut self, interval: Duration, observer: impl FnMut(&Progress) + 'a) -> Self {
+        self.observer = Some((Box::new(observer), interval));
+        self
+    }
+
+    /// Lets the search finish by `plan` from any state on its way.
+    /// Searches that find optimal solutions only do so once nothing
+    /// shorter is left, and go by the states it explored to see how
+    /// far from solved others are.
+    pub const fn towards(mut self, plan: &'a Plan) -> Self {
+        self.plan = Some(plan);
+        self
+    }
+
+    /// Counters for everything the search did so far
+    pub fn stats(&self) -> Stats {
+        Stats {
+            generated: self.generated,
+            expanded: self.expanded,
+            duplicates: self.duplicates,
+            peak_visited: self.peak_visited,
+            visited_bytes: self.peak_visited * self.entry_bytes,
+            max_depth: self.depth,
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    /// What a breadth-first search that found an optimal solution
+    /// explored, for a [`Plan`] to keep
+    pub fn take_explored(&mut self) -> Option<Explored> {
+        self.explored.take()
+    }
+
+    /// The rest of the plan from `p`, if it is on the way
+    fn planned(&self, p: &Packed) -> Option<&[(u8, u8)]> {
+        self.plan
+            .and_then(|plan| plan.rest.get(p))
+            .map(Vec::as_slice)
+    }
+
+    /// Keeps `shortcut` the shortest way found to finish by the plan,
+    /// with `moves` the chain leading to `p`
+    fn shortcut(
+        &self,
+        p: &Packed,
+        shortcut: &mut Option<ValidMoves>,
+        moves: impl FnOnce() -> ValidMoves,
+    ) {
+        let Some(rest) = self.planned(p) else {
+            return;
+        };
+        let mut moves = moves();
+        moves.0.extend_from_slice(rest);
+        if shortcut.as_ref().is_none_or(|s| moves.0.len() < s.0.len()) {
+            *shortcut = Some(moves);
+        }
+    }
+
+    /// [`heuristic`], raised to what the plan explored where it can be
+    fn estimate(&self, p: &Puzzle) -> usize {
+        let bound = self
+            .plan
+            .and_then(|plan| plan.explored.as_ref())
+            .map_or(0, |e| e.bound(&Packed::from(p).canonical()));
+        heuristic(p).max(bound)
+    }
+
+    /// Keeps the states a breadth-first search reached, by the moves
+    /// it took, once it is sure it found a solution `length` long
+    fn settle(&mut self, length: usize, depths: HashMap<Packed, usize>) {
+        self.explored = Some(Explored { length, depths });
+    }
+
+    /// Checks that there is something to search for
+    fn begin(&mut self, p: &Puzzle) -> Result<(), NoSolution> {
+        self.begin_hidden(p)?;
+        if p.has_unknown() {
+            return Err(NoSolution::HasUnknown(p.clone(), ValidMoves(vec![])));
+        }
+        Ok(())
+    }
+
+    /// [`Self::begin`] for searches that can cope with unknown cells
+    fn begin_hidden(&mut self, p: &Puzzle) -> Result<(), NoSolution> {
+        // A search can be run more than once, nothing from before counts
+        self.best = None;
+        self.depth_limited = false;
+        self.explored = None;
+        if p.is_solved() {
+            return Err(NoSolution::AlreadySolved);
+        }
+        p.validate().map_err(NoSolution::Invalid)?;
+        self.entry_bytes = Packed::from(p).bytes();
+        Ok(())
+    }
+
+    /// Counts a node `moves` moves deep about to be expanded,
+    /// unless some part of the budget has run out
+    fn expand(&mut self, moves: usize, frontier: usize, visited: usize) -> Result<(), Limit> {
+        self.budget.check(self.started, self.expanded)?;
+        self.expanded += 1;
+        self.depth = self.depth.max(moves);
+        self.peak_visited = self.peak_visited.max(visited);
+        self.report(frontier, visited);
+        Ok(())
+    }
+
+    /// Counts child nodes created
+    fn generate(&mut self, n: usize) {
+        self.generated += n;
+    }
+
+    /// Counts a node dropped for repeating an earlier state
+    fn prune(&mut self) {
+        self.duplicates += 1;
+    }
+
+    fn report(&mut self, frontier: usize, visited: usize) {
+        let Some((observer, interval)) = self.observer.as_mut() else {
+            return;
+        };
+        if self.reported.elapsed() < *interval {
+            return;
+        }
+        self.reported = Instant::now();
+        observer(&Progress {
+            expanded: self.expanded,
+            frontier,
+            visited,
+            depth: self.depth,
+            elapsed: self.started.elapsed(),
+        });
     }
-    if p.has_unknown() {
-        return Err(NoSolution::HasUnknown(p.clone(), ValidMoves(vec![])));
+
+    fn too_deep(&mut self, moves: usize) -> bool {
+        let too_deep = self.budget.beyond(moves);
+        self.depth_limited |= too_deep;
+        too_deep
+    }
+
+    /// Remembers the chain of moves leading to `p`
+    /// if it is the closest to solved so far
+    fn offer(&mut self, p: &Puzzle, moves: impl FnOnce() -> ValidMoves) {
+        self.consider(heuristic(p), moves);
+    }
+
+    fn consider(&mut self, h: usize, moves: impl FnOnce() -> ValidMoves) {
+        if self.best.as_ref().is_none_or(|(best, _)| h < *best) {
+            self.best = Some((h, moves()));
+        }
+    }
+
+    fn out_of_budget(&mut self, limit: Limit) -> NoSolution {
+        let moves = self.best.take().map_or(ValidMoves(vec![]), |b| b.1);
+        NoSolution::OutOfBudget(moves, limit)
+    }
+
+    /// The search ran dry. That only proves there is no solution
+    /// if nothing was cut off by the depth limit.
+    fn exhausted(&mut self, moves: ValidMoves, max_depth: usize) -> NoSolution {
+        if self.depth_limited {
+            self.out_of_budget(Limit::Depth)
+        } else {
+            NoSolution::CannotBeSolved(moves, max_depth)
+        }
+    }
+}
+
+/// Which moves [`dfs_puzzle`] tries first. With every rule off the
+/// moves are tried in the order [`Puzzle::valid_moves`] lists them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveOrder {
+    /// Moves that fill a tube with a single colour
+    pub complete: bool,
+    /// Pouring onto the same colour over pouring into an empty tube
+    pub matching: bool,
+    /// Avoid moves that break up a tube holding a single colour
+    pub keep_runs: bool,
+}
+
+impl Default for MoveOrder {
+    fn default() -> Self {
+        Self {
+            complete: true,
+            matching: true,
+            keep_runs: true,
+        }
+    }
+}
+
+impl MoveOrder {
+    pub const NONE: Self = Self {
+        complete: false,
+        matching: false,
+        keep_runs: false,
+    };
+
+    /// How promising a move looks, higher is better
+    fn score(self, p: &Puzzle, m: (u8, u8)) -> isize {
+        let (from, to) = (p.tubes()[m.0 as usize], p.tubes()[m.1 as usize]);
+        let mut after = p.clone();
+        if after.pour(m.0.into(), m.1.into()).is_err() {
+            return 0;
+        }
+        let filled = after.tubes()[m.1 as usize];
+        let mut score = 0;
+        if self.complete && filled.is_complete() {
+            score += 4;
+        }
+        if self.matching && !to.is_empty() {
+            score += 2;
+        }
+        if self.keep_runs && from.num_runs() == 1 && filled.num_runs() > 1 {
+            score -= 1;
+        }
+        score
+    }
+
+    /// Sorts `moves` so the most promising comes last, where
+    /// it is popped off a stack first. Ties keep their order.
+    fn sort(self, p: &Puzzle, moves: &mut [(u8, u8)]) {
+        if self != Self::NONE {
+            moves.sort_by_cached_key(|&m| self.score(p, m));
+        }
     }
+}
+
+/// Depth-first search in this order
+impl Solver for MoveOrder {
+    fn solve(&self, p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+        dfs_puzzle(p, *self, search)
+    }
+}
+
+/// Depth-first search, trying the moves `order` rates
+/// most promising first
+pub fn dfs_puzzle(
+    p: &Puzzle,
+    order: MoveOrder,
+    search: &mut Search,
+) -> Result<ValidMoves, NoSolution> {
+    search.begin(p)?;
 
     let mut arena: Arena<PuzzleState> = Arena::new();
     let mut stack: VecDeque<NodeId> = VecDeque::new();
 
-    for m in &p.valid_moves().get() {
+    let mut roots = p.valid_moves().get();
+    order.sort(p, &mut roots);
+    search.generate(roots.len());
+    for m in &roots {
         let mut new_p = p.clone();
         new_p.pour(m.0.into(), m.1.into()).unwrap();
         let node = arena.new_node(PuzzleState {
-            puzzle: new_p.clone(),
+            puzzle: Packed::from(&new_p),
             played_move: *m,
             depth: 0,
         });
@@ -40,80 +543,1748 @@ pub fn dfs_puzzle(p: &Puzzle) -> Result<ValidMoves, NoSolution> {
     let mut max_depth = 0;
     let mut max_moves = ValidMoves(vec![]);
 
-    let mut visited = HashSet::<Puzzle>::new();
+    let mut visited = HashSet::<Packed>::new();
 
     while let Some(id) = stack.pop_back() {
         let node = &arena[id];
-        let puzzle = node.data.puzzle.clone();
+        let puzzle = Puzzle::from(&node.get().puzzle);
         if puzzle.is_solved() {
             return Ok(get_move_chain(&arena, id));
         }
-        if p.has_unknown() {
-            return Err(NoSolution::HasUnknown(
-                p.clone(),
-                get_move_chain(&arena, id),
-            ));
+        if puzzle.has_unknown() {
+            return Err(NoSolution::HasUnknown(puzzle, get_move_chain(&arena, id)));
         }
-        if visited.contains(&puzzle) {
+        if let Some(rest) = search.planned(&node.get().puzzle) {
+            let mut moves = get_move_chain(&arena, id);
+            moves.0.extend_from_slice(rest);
+            return Ok(moves);
+        }
+        if !visited.insert(node.get().puzzle.canonical()) {
+            search.prune();
             continue;
         }
-        visited.insert(puzzle.clone());
+        let depth = node.get().depth;
+        if let Err(limit) = search.expand(depth + 1, stack.len(), visited.len()) {
+            return Err(search.out_of_budget(limit));
+        }
+        search.offer(&puzzle, || get_move_chain(&arena, id));
 
-        let moves = puzzle.valid_moves().get();
+        let mut moves = puzzle.valid_moves().get();
         if moves.is_empty() {
-            if node.data.depth > max_depth {
+            if depth > max_depth {
                 max_moves = get_move_chain(&arena, id);
-                max_depth = node.data.depth;
-                dbg!(max_depth);
+                max_depth = depth;
             }
-            arena.remove_node(id);
+            id.remove(&mut arena);
+            continue;
+        }
+        if search.too_deep(depth + 1) {
             continue;
         }
 
+        order.sort(&puzzle, &mut moves);
+        search.generate(moves.len());
         for m in &moves {
             let mut new_p = puzzle.clone();
             new_p.pour(m.0.into(), m.1.into()).unwrap();
             let child_id = arena.new_node(PuzzleState {
-                puzzle: new_p.clone(),
+                puzzle: Packed::from(&new_p),
                 played_move: *m,
-                depth: arena[id].data.depth + 1,
+                depth: depth + 1,
             });
-            match id.append(child_id, &mut arena) {
-                Ok(_) => stack.push_back(child_id),
-                Err(err) => {
-                    dbg!(err);
-                }
+            if id.checked_append(child_id, &mut arena).is_ok() {
+                stack.push_back(child_id);
             }
         }
     }
 
-    Err(NoSolution::CannotBeSolved(max_moves, max_depth))
+    Err(search.exhausted(max_moves, max_depth))
 }
 
-fn get_move_chain(arena: &Arena<PuzzleState>, id: NodeId) -> ValidMoves {
-    let mut moves: Vec<(u8, u8)> = id
-        .ancestors(arena)
-        .into_iter()
-        .map(|d| arena[d].data.played_move)
-        .collect();
-    moves.reverse();
-    ValidMoves(moves)
+/// Breadth-first search, so the first solved state dequeued
+/// is reached by a minimum-length chain of moves.
+pub fn bfs_puzzle(p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+    search.begin(p)?;
+
+    let mut arena: Arena<PuzzleState> = Arena::new();
+    let mut queue: VecDeque<NodeId> = VecDeque::new();
+    // By the moves it took to first reach each state
+    let mut visited = HashMap::<Packed, usize>::new();
+    visited.insert(Packed::from(p).canonical(), 0);
+
+    let roots = p.valid_moves().get();
+    search.generate(roots.len());
+    for m in &roots {
+        let mut new_p = p.clone();
+        new_p.pour(m.0.into(), m.1.into()).unwrap();
+        let packed = Packed::from(&new_p);
+        if visited.insert(packed.canonical(), 1).is_some() {
+            search.prune();
+            continue;
+        }
+        let node = arena.new_node(PuzzleState {
+            puzzle: packed,
+            played_move: *m,
+            depth: 0,
+        });
+        queue.push_back(node);
+    }
+
+    let mut max_depth = 0;
+    let mut max_moves = ValidMoves(vec![]);
+    let mut shortcut = None;
+
+    while let Some(id) = queue.pop_front() {
+        let depth = arena[id].get().depth;
+        // Anything found from here on is at least as long
+        if let Some(moves) = shortcut.take_if(|s: &mut ValidMoves| s.0.len() <= depth + 1) {
+            search.settle(moves.0.len(), visited);
+            return Ok(moves);
+        }
+        let puzzle = Puzzle::from(&arena[id].get().puzzle);
+        if puzzle.is_solved() {
+            search.settle(depth + 1, visited);
+            return Ok(get_move_chain(&arena, id));
+        }
+        if puzzle.has_unknown() {
+            return Err(NoSolution::HasUnknown(puzzle, get_move_chain(&arena, id)));
+        }
+        search.shortcut(&arena[id].get().puzzle, &mut shortcut, || {
+            get_move_chain(&arena, id)
+        });
+        if let Err(limit) = search.expand(depth + 1, queue.len(), visited.len()) {
+            return Err(search.out_of_budget(limit));
+        }
+        search.offer(&puzzle, || get_move_chain(&arena, id));
+
+        let moves = puzzle.valid_moves().get();
+        if moves.is_empty() && depth > max_depth {
+            max_moves = get_move_chain(&arena, id);
+            max_depth = depth;
+        }
+        if search.too_deep(depth + 1) {
+            continue;
+        }
+
+        search.generate(moves.len());
+        for m in &moves {
+            let mut new_p = puzzle.clone();
+            new_p.pour(m.0.into(), m.1.into()).unwrap();
+            // Marking on discovery keeps each state queued at most once,
+            // at the shallowest depth it can be reached
+            let packed = Packed::from(&new_p);
+            if visited.insert(packed.canonical(), depth + 2).is_some() {
+                search.prune();
+                continue;
+            }
+            let child_id = arena.new_node(PuzzleState {
+                puzzle: packed,
+                played_move: *m,
+                depth: depth + 1,
+            });
+            if id.checked_append(child_id, &mut arena).is_ok() {
+                queue.push_back(child_id);
+            }
+        }
+    }
+
+    // Cut short by the depth limit, so it may not be the shortest
+    if let Some(moves) = shortcut {
+        return Ok(moves);
+    }
+    Err(search.exhausted(max_moves, max_depth))
 }
 
-#[cfg(test)]
-mod solve_test {
-    use crate::puzzle::Puzzle;
-    use crate::solve::dfs_puzzle;
-    use crate::state::State::Water;
-    use crate::water::Water::{Blue, Green, Red};
+/// What [`reveal_puzzle`] looks for
+#[derive(Clone, Copy, Default)]
+pub enum Reveal {
+    /// The fewest pours that uncover any hidden cell
+    #[default]
+    Next,
+    /// The pours that uncover the most hidden cells at once,
+    /// fewest first
+    Most,
+}
 
-    #[test]
-    fn test_solve() {
-        let mut p = Puzzle::new(5);
-        p.set_whole_tube(0, [Water(Green), Water(Blue), Water(Red), Water(Green)]);
-        p.set_whole_tube(1, [Water(Blue), Water(Blue), Water(Red), Water(Green)]);
-        p.set_whole_tube(2, [Water(Blue), Water(Red), Water(Red), Water(Green)]);
+impl Solver for Reveal {
+    fn solve(&self, p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+        reveal_puzzle(p, *self, search)
+    }
+}
+
+/// Plans pours for a puzzle with unknown cells in it.
+///
+/// A hidden cell is uncovered once everything above it has been
+/// poured away, which leaves it on top of its tube. Its colour has
+/// to be looked up in the game before going any further, so such
+/// tubes are left alone from then on. A plan only counts if it
+/// leaves an empty tube or some pour to make, so whatever turns up
+/// there is still something to be done about it.
+pub fn reveal_puzzle(
+    p: &Puzzle,
+    goal: Reveal,
+    search: &mut Search,
+) -> Result<ValidMoves, NoSolution> {
+    search.begin_hidden(p)?;
+    let hidden = uncovered(p);
+
+    let mut arena: Arena<PuzzleState> = Arena::new();
+    let mut queue: VecDeque<NodeId> = VecDeque::new();
+    let mut visited = HashSet::<Packed>::new();
+    visited.insert(Packed::from(p).canonical());
+
+    let roots = p.valid_moves().get();
+    search.generate(roots.len());
+    for m in &roots {
+        let mut new_p = p.clone();
+        new_p.pour(m.0.into(), m.1.into()).unwrap();
+        let packed = Packed::from(&new_p);
+        if !visited.insert(packed.canonical()) {
+            search.prune();
+            continue;
+        }
+        let node = arena.new_node(PuzzleState {
+            puzzle: packed,
+            played_move: *m,
+            depth: 0,
+        });
+        queue.push_back(node);
+    }
+
+    let mut best: Option<(usize, ValidMoves)> = None;
+    while let Some(id) = queue.pop_front() {
+        let puzzle = Puzzle::from(&arena[id].get().puzzle);
+        let revealed = uncovered(&puzzle) - hidden;
+        // Breadth-first, so only strictly more makes a better plan
+        if revealed > best.as_ref().map_or(0, |b| b.0) && recoverable(&puzzle) {
+            let moves = get_move_chain(&arena, id);
+            if let Reveal::Next = goal {
+                return Ok(moves);
+            }
+            best = Some((revealed, moves));
+        }
+        let depth = arena[id].get().depth;
+        if let Err(limit) = search.expand(depth + 1, queue.len(), visited.len()) {
+            return match best {
+                Some((_, moves)) => Err(NoSolution::OutOfBudget(moves, limit)),
+                None => Err(search.out_of_budget(limit)),
+            };
+        }
+        if search.too_deep(depth + 1) {
+            continue;
+        }
+
+        let moves = puzzle.valid_moves().get();
+        search.generate(moves.len());
+        for m in &moves {
+            let mut new_p = puzzle.clone();
+            new_p.pour(m.0.into(), m.1.into()).unwrap();
+            let packed = Packed::from(&new_p);
+            if !visited.insert(packed.canonical()) {
+                search.prune();
+                continue;
+            }
+            let child_id = arena.new_node(PuzzleState {
+                puzzle: packed,
+                played_move: *m,
+                depth: depth + 1,
+            });
+            if id.checked_append(child_id, &mut arena).is_ok() {
+                queue.push_back(child_id);
+            }
+        }
+    }
+
+    best.map(|(_, moves)| moves)
+        .ok_or(NoSolution::NothingToReveal)
+}
+
+/// Tubes with a hidden cell on top
+fn uncovered(p: &Puzzle) -> usize {
+    p.tubes()
+        .iter()
+        .filter(|t| t.top() == State::Unknown)
+        .count()
+}
+
+/// Whether there's anything left to do, whatever colour turns up
+fn recoverable(p: &Puzzle) -> bool {
+    p.tubes().iter().any(|t| t.is_empty()) || !p.valid_moves().0.is_empty()
+}
+
+/// Lower bound on the number of moves left.
+///
+/// Every run sitting on top of another colour has to be poured
+/// away at least once, as do the runs of a colour resting at the
+/// bottom of more tubes than that colour fills. A pour only ever
+/// moves one such run for the first time, so this never overestimates.
+pub fn heuristic(p: &Puzzle) -> usize {
+    let mut bottoms: HashMap<_, usize> = HashMap::new();
+    let mut moved = 0;
+    for tube in p.tubes() {
+        let runs = tube.num_runs();
+        if runs == 0 {
+            continue;
+        }
+        moved += runs - 1;
+        if let State::Water(w) = tube.bottom() {
+            *bottoms.entry(w).or_insert(0) += 1;
+        }
+    }
+    let counts = p.colour_counts();
+    let spare = bottoms.iter().map(|(w, &n)| {
+        let fills = counts.get(w).map_or(0, |c| c / p.capacity()).max(1);
+        n.saturating_sub(fills)
+    });
+    moved + spare.sum::<usize>()
+}
+
+struct Open {
+    cost: usize,
+    heuristic: usize,
+    id: NodeId,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    // BinaryHeap is a max-heap, so the cheapest node has to compare greatest.
+    // Ties go to the node closest to the goal.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .cmp(&self.cost)
+            .then_with(|| other.heuristic.cmp(&self.heuristic))
+    }
+}
+
+/// A* search ordered by moves played plus [`heuristic`].
+/// The heuristic is consistent, so the first solved state
+/// popped is reached by a minimum-length chain of moves.
+pub fn astar_puzzle(p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+    best_first(p, search, false)
+}
+
+/// Greedy best-first search, always expanding whichever state
+/// [`heuristic`] rates closest to solved. Usually quicker than
+/// A*, but the solution can be longer than it needs to be.
+pub fn greedy_puzzle(p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+    best_first(p, search, true)
+}
+
+/// Ordered by `g + h` for A*, or just `h` when `greedy`
+fn best_first(p: &Puzzle, search: &mut Search, greedy: bool) -> Result<ValidMoves, NoSolution> {
+    search.begin(p)?;
+    let cost = |g: usize, h: usize| if greedy { h } else { g + h };
+
+    let mut arena: Arena<PuzzleState> = Arena::new();
+    let mut open = BinaryHeap::new();
+    let mut best = HashMap::<Packed, usize>::new();
+    let mut closed = HashSet::<Packed>::new();
+    best.insert(Packed::from(p).canonical(), 0);
+    closed.insert(Packed::from(p).canonical());
+
+    let roots = p.valid_moves().get();
+    search.generate(roots.len());
+    for m in &roots {
+        let mut new_p = p.clone();
+        new_p.pour(m.0.into(), m.1.into()).unwrap();
+        let h = search.estimate(&new_p);
+        let packed = Packed::from(&new_p);
+        best.insert(packed.canonical(), 1);
+        let id = arena.new_node(PuzzleState {
+            puzzle: packed,
+            played_move: *m,
+            depth: 0,
+        });
+        open.push(Open {
+            cost: cost(1, h),
+            heuristic: h,
+            id,
+        });
+    }
+
+    let mut max_depth = 0;
+    let mut max_moves = ValidMoves(vec![]);
+    let mut shortcut = None;
+
+    while let Some(Open { cost: f, id, .. }) = open.pop() {
+        let puzzle = Puzzle::from(&arena[id].get().puzzle);
+        if puzzle.is_solved() {
+            return Ok(get_move_chain(&arena, id));
+        }
+        if puzzle.has_unknown() {
+            return Err(NoSolution::HasUnknown(puzzle, get_move_chain(&arena, id)));
+        }
+        // Stale entry for a state that was reached more cheaply since
+        if !closed.insert(arena[id].get().puzzle.canonical()) {
+            search.prune();
+            continue;
+        }
+        // Nothing left to find is shorter than the cheapest open node,
+        // and greedy search doesn't care
+        search.shortcut(&arena[id].get().puzzle, &mut shortcut, || {
+            get_move_chain(&arena, id)
+        });
+        if let Some(moves) = shortcut.take_if(|s| greedy || s.0.len() <= f) {
+            return Ok(moves);
+        }
+        let depth = arena[id].get().depth;
+        if let Err(limit) = search.expand(depth + 1, open.len(), closed.len()) {
+            return Err(search.out_of_budget(limit));
+        }
+        search.offer(&puzzle, || get_move_chain(&arena, id));
+
+        let moves = puzzle.valid_moves().get();
+        if moves.is_empty() && depth > max_depth {
+            max_moves = get_move_chain(&arena, id);
+            max_depth = depth;
+        }
+        if search.too_deep(depth + 1) {
+            continue;
+        }
+
+        // depth is zero-based, so children are `depth + 2` moves in
+        let g = depth + 2;
+        search.generate(moves.len());
+        for m in &moves {
+            let mut new_p = puzzle.clone();
+            new_p.pour(m.0.into(), m.1.into()).unwrap();
+            let packed = Packed::from(&new_p);
+            let key = packed.canonical();
+            if closed.contains(&key) || best.get(&key).is_some_and(|&b| b <= g) {
+                search.prune();
+                continue;
+            }
+            best.insert(key, g);
+            let h = search.estimate(&new_p);
+            let child_id = arena.new_node(PuzzleState {
+                puzzle: packed,
+                played_move: *m,
+                depth: depth + 1,
+            });
+            if id.checked_append(child_id, &mut arena).is_ok() {
+                open.push(Open {
+                    cost: cost(g, h),
+                    heuristic: h,
+                    id: child_id,
+                });
+            }
+        }
+    }
+
+    if let Some(moves) = shortcut {
+        return Ok(moves);
+    }
+    Err(search.exhausted(max_moves, max_depth))
+}
+
+/// Iterative deepening A*. Only the current path is kept in memory,
+/// at the cost of re-expanding states on every iteration.
+pub fn idastar_puzzle(p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+    search.begin(p)?;
+
+    let mut path = HashSet::from([Packed::from(p).canonical()]);
+    let mut moves = vec![];
+    let mut threshold = search.estimate(p);
+    loop {
+        match ida_search(p, search, &mut path, &mut moves, 0, threshold) {
+            Deepen::Found => return Ok(ValidMoves(moves)),
+            Deepen::Uncovered(puzzle) => {
+                return Err(NoSolution::HasUnknown(puzzle, ValidMoves(moves)))
+            }
+            Deepen::Exhausted => return Err(search.exhausted(ValidMoves(vec![]), 0)),
+            Deepen::Exceeded(next) => threshold = next,
+            Deepen::Stopped(limit) => return Err(search.out_of_budget(limit)),
+        }
+    }
+}
+
+enum Deepen {
+    Found,
+    /// Reached a state with something hidden on top, by the moves so far
+    Uncovered(Puzzle),
+    Exhausted,
+    Exceeded(usize),
+    Stopped(Limit),
+}
+
+fn ida_search(
+    puzzle: &Puzzle,
+    search: &mut Search,
+    path: &mut HashSet<Packed>,
+    moves: &mut Vec<(u8, u8)>,
+    g: usize,
+    threshold: usize,
+) -> Deepen {
+    // Checked before the threshold, since the cells left hidden make
+    // such states look far from solved and they'd only turn up late
+    if puzzle.has_unknown() {
+        return Deepen::Uncovered(puzzle.clone());
+    }
+    let f = g + search.estimate(puzzle);
+    if f > threshold {
+        return Deepen::Exceeded(f);
+    }
+    if puzzle.is_solved() {
+        return Deepen::Found;
+    }
+    // Every threshold is at most the length of an optimal solution,
+    // so finishing by the plan within it is optimal too
+    if let Some(rest) = search
+        .plan
+        .and_then(|plan| plan.rest.get(&Packed::from(puzzle)))
+        .filter(|rest| g + rest.len() <= threshold)
+    {
+        moves.extend_from_slice(rest);
+        return Deepen::Found;
+    }
+    if let Err(limit) = search.expand(g, 0, path.len()) {
+        return Deepen::Stopped(limit);
+    }
+    search.offer(puzzle, || ValidMoves(moves.clone()));
+    if search.too_deep(g) {
+        return Deepen::Exhausted;
+    }
+
+    let mut next = None;
+    let children = puzzle.valid_moves().get();
+    search.generate(children.len());
+    for m in children {
+        let mut new_p = puzzle.clone();
+        new_p.pour(m.0.into(), m.1.into()).unwrap();
+        let key = Packed::from(&new_p).canonical();
+        if path.contains(&key) {
+            search.prune();
+            continue;
+        }
+        path.insert(key.clone());
+        moves.push(m);
+        match ida_search(&new_p, search, path, moves, g + 1, threshold) {
+            Deepen::Found => return Deepen::Found,
+            Deepen::Uncovered(p) => return Deepen::Uncovered(p),
+            Deepen::Exceeded(f) => next = Some(next.map_or(f, |n: usize| n.min(f))),
+            Deepen::Exhausted => {}
+            Deepen::Stopped(limit) => return Deepen::Stopped(limit),
+        }
+        path.remove(&key);
+        moves.pop();
+    }
+    next.map_or(Deepen::Exhausted, Deepen::Exceeded)
+}
+
+/// What [`all_solutions`] turned up
+pub struct Solutions {
+    /// Distinct optimal solutions, in [`normal_form`]
+    pub found: Vec<ValidMoves>,
+    /// Moves in an optimal solution
+    pub length: usize,
+    /// Stopped at the most asked for, so there may be more
+    pub capped: bool,
+    /// Ran out of budget partway through
+    pub stopped: Option<Limit>,
+}
+
+/// Enumerates the optimal solutions, up to `max` of them.
+///
+/// Chains that only differ by the order of [`independent`] pours
+/// count once. The optimal length comes from [`astar_puzzle`],
+/// then every chain of that length is tried, cut short wherever
+/// [`heuristic`] says it can't finish in time.
+pub fn all_solutions(
+    p: &Puzzle,
+    max: Option<usize>,
+    search: &mut Search,
+) -> Result<Solutions, NoSolution> {
+    let length = astar_puzzle(p, search)?.0.len();
+    let mut e = Enumerate {
+        max,
+        length,
+        found: vec![],
+        seen: HashSet::new(),
+        dead: HashSet::new(),
+        moves: vec![],
+    };
+    let stopped = enumerate(p, search, &mut e).err();
+    Ok(Solutions {
+        capped: max.is_some_and(|max| e.found.len() >= max),
+        found: e.found,
+        length,
+        stopped,
+    })
+}
+
+/// A state, the moves played to get there and the last of them
+type Dead = (Packed, usize, Option<(u8, u8)>);
+
+struct Enumerate {
+    max: Option<usize>,
+    length: usize,
+    found: Vec<ValidMoves>,
+    seen: HashSet<Vec<(u8, u8)>>,
+    /// States that can't be finished in the moves left after the
+    /// given last move, which decides the moves tried from them
+    dead: HashSet<Dead>,
+    moves: Vec<(u8, u8)>,
+}
+
+impl Enumerate {
+    fn full(&self) -> bool {
+        self.max.is_some_and(|max| self.found.len() >= max)
+    }
+}
+
+/// Whether any solution goes through `puzzle`
+fn enumerate(puzzle: &Puzzle, search: &mut Search, e: &mut Enumerate) -> Result<bool, Limit> {
+    let g = e.moves.len();
+    if puzzle.is_solved() {
+        let normal = normal_form(&e.moves);
+        if e.seen.insert(normal.clone()) {
+            e.found.push(ValidMoves(normal));
+        }
+        return Ok(true);
+    }
+    if g + search.estimate(puzzle) > e.length {
+        return Ok(false);
+    }
+    let last = e.moves.last().copied();
+    let key = (Packed::from(puzzle), g, last);
+    if e.dead.contains(&key) {
+        search.prune();
+        return Ok(false);
+    }
+    search.expand(g, 0, e.dead.len())?;
+
+    let mut any = false;
+    let children = puzzle.valid_moves().get();
+    search.generate(children.len());
+    for m in children {
+        // The other order is tried too, and is the one in normal form
+        if last.is_some_and(|l| independent(l, m) && m < l) {
+            continue;
+        }
+        let mut new_p = puzzle.clone();
+        new_p.pour(m.0.into(), m.1.into()).unwrap();
+        e.moves.push(m);
+        any |= enumerate(&new_p, search, e)?;
+        e.moves.pop();
+        if e.full() {
+            return Ok(true);
+        }
+    }
+    if !any {
+        e.dead.insert(key);
+    }
+    Ok(any)
+}
+
+pub struct Parallel {
+    pub threads: usize,
+    /// Trade the work-stealing search for a level-by-level one whose
+    /// result doesn't depend on thread scheduling (and is optimal)
+    pub deterministic: bool,
+}
+
+impl Default for Parallel {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+            deterministic: false,
+        }
+    }
+}
+
+/// Moves played from the root. Siblings share their parent's chain,
+/// so nodes can be handed between threads without an arena.
+struct Chain {
+    played_move: (u8, u8),
+    parent: Option<Arc<Chain>>,
+    depth: usize,
+}
+
+impl Chain {
+    fn moves(self: &Arc<Self>) -> ValidMoves {
+        let mut moves = vec![];
+        let mut node = Some(self);
+        while let Some(c) = node {
+            moves.push(c.played_move);
+            node = c.parent.as_ref();
+        }
+        moves.reverse();
+        ValidMoves(moves)
+    }
+}
+
+type Task = (Packed, Arc<Chain>);
+
+fn children(puzzle: &Puzzle, parent: Option<&Arc<Chain>>) -> Vec<Task> {
+    let depth = parent.map_or(0, |c| c.depth + 1);
+    puzzle
+        .valid_moves()
+        .get()
+        .into_iter()
+        .map(|m| {
+            let mut new_p = puzzle.clone();
+            new_p.pour(m.0.into(), m.1.into()).unwrap();
+            let chain = Chain {
+                played_move: m,
+                parent: parent.cloned(),
+                depth,
+            };
+            (Packed::from(&new_p), Arc::new(chain))
+        })
+        .collect()
+}
+
+/// Searches on `options.threads` threads at once.
+///
+/// By default each thread runs its own depth-first search and steals
+/// from the bottom of another thread's stack once its own runs dry,
+/// with all of them sharing one visited set. The first solution found
+/// wins, so it can differ between runs. A `deterministic` search
+/// expands one level of a breadth-first search at a time instead.
+pub fn parallel_puzzle(
+    p: &Puzzle,
+    options: &Parallel,
+    search: &mut Search,
+) -> Result<ValidMoves, NoSolution> {
+    search.begin(p)?;
+
+    let threads = options.threads.max(1);
+    let roots = children(p, None);
+    search.generate(roots.len());
+    if options.deterministic {
+        parallel_bfs(p, roots, threads, search)
+    } else {
+        parallel_dfs(roots, threads, search)
+    }
+}
+
+/// A way of searching for a solution
+pub trait Solver {
+    fn solve(&self, p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution>;
+}
+
+impl<F> Solver for F
+where
+    F: Fn(&Puzzle, &mut Search) -> Result<ValidMoves, NoSolution>,
+{
+    fn solve(&self, p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+        self(p, search)
+    }
+}
+
+impl Solver for Parallel {
+    fn solve(&self, p: &Puzzle, search: &mut Search) -> Result<ValidMoves, NoSolution> {
+        parallel_puzzle(p, self, search)
+    }
+}
+
+/// Solves `p` with as few extra empty tubes as it takes, up to `max`.
+///
+/// Another tube is only added once `solver` proves there is no
+/// solution without it, and the budget of `search` covers every
+/// attempt. Returns the puzzle with the tubes added and its solution.
+pub fn extra_tubes(
+    p: &Puzzle,
+    max: usize,
+    solver: &dyn Solver,
+    search: &mut Search,
+) -> Result<(Puzzle, ValidMoves), NoSolution> {
+    let mut p = p.clone();
+    let mut extra = 0;
+    loop {
+        match solver.solve(&p, search) {
+            Ok(moves) => return Ok((p, moves)),
+            Err(NoSolution::CannotBeSolved(..) | NoSolution::Invalid(Invalid::NoSpace))
+                if extra < max =>
+            {
+                p.add_tube();
+                extra += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A solver that can be picked by name with `solve <name>`
+pub struct Strategy {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// Arguments taken after the name, for the usage text
+    pub args: &'static str,
+    pub about: &'static str,
+    /// Makes the solver from the arguments after its name,
+    /// or `None` if they don't make sense for it
+    pub build: fn(&[&str]) -> Option<Box<dyn Solver>>,
+}
+
+/// Every strategy `solve` knows about. The first is the default.
+/// New ones only need adding here to show up in the REPL.
+pub const STRATEGIES: &[Strategy] = &[
+    Strategy {
+        name: "dfs",
+        aliases: &[],
+        args: "[complete] [matching] [runs] [unordered]",
+        about: "depth-first search, finds some solution quickly. \
+                naming move ordering rules uses only those",
+        build: |args| {
+            let mut order = if args.is_empty() {
+                MoveOrder::default()
+            } else {
+                MoveOrder::NONE
+            };
+            for arg in args {
+                match *arg {
+                    "complete" => order.complete = true,
+                    "matching" => order.matching = true,
+                    "runs" => order.keep_runs = true,
+                    "unordered" => {}
+                    _ => return None,
+                }
+            }
+            Some(Box::new(order))
+        },
+    },
+    Strategy {
+        name: "bfs",
+        aliases: &["optimal"],
+        args: "",
+        about: "breadth-first search, finds a shortest solution",
+        build: |args| {
+            args.is_empty()
+                .then(|| Box::new(bfs_puzzle) as Box<dyn Solver>)
+        },
+    },
+    Strategy {
+        name: "astar",
+        aliases: &["a*"],
+        args: "",
+        about: "A* search, finds a shortest solution expanding fewer states than bfs",
+        build: |args| {
+            args.is_empty()
+                .then(|| Box::new(astar_puzzle) as Box<dyn Solver>)
+        },
+    },
+    Strategy {
+        name: "idastar",
+        aliases: &["ida*"],
+        args: "",
+        about: "iterative deepening A*, finds a shortest solution in little memory",
+        build: |args| {
+            args.is_empty()
+                .then(|| Box::new(idastar_puzzle) as Box<dyn Solver>)
+        },
+    },
+    Strategy {
+        name: "greedy",
+        aliases: &[],
+        args: "",
+        about: "greedy best-first search, quick but the solution may be long",
+        build: |args| {
+            args.is_empty()
+                .then(|| Box::new(greedy_puzzle) as Box<dyn Solver>)
+        },
+    },
+    Strategy {
+        name: "reveal",
+        aliases: &[],
+        args: "[next|most]",
+        about: "pours that uncover hidden cells, the next one soonest or the most at once",
+        build: |args| match args {
+            [] | ["next"] => Some(Box::new(Reveal::Next)),
+            ["most"] => Some(Box::new(Reveal::Most)),
+            _ => None,
+        },
+    },
+    Strategy {
+        name: "parallel",
+        aliases: &[],
+        args: "[<threads>] [deterministic]",
+        about: "depth-first search on several threads, or breadth-first when deterministic",
+        build: |args| {
+            let mut options = Parallel::default();
+            for arg in args {
+                match *arg {
+                    "deterministic" | "det" => options.deterministic = true,
+                    n => options.threads = n.parse().ok().filter(|&n| n > 0)?,
+                }
+            }
+            Some(Box::new(options))
+        },
+    },
+];
+
+/// Looks a strategy up by its name or one of its aliases
+pub fn strategy(name: &str) -> Option<&'static Strategy> {
+    STRATEGIES
+        .iter()
+        .find(|s| s.name == name || s.aliases.contains(&name))
+}
+
+/// What a worker thread found out besides a solution
+#[derive(Default)]
+struct Worker {
+    deepest: Option<Arc<Chain>>,
+    best: Option<(usize, Arc<Chain>)>,
+    depth_limited: bool,
+    generated: usize,
+    duplicates: usize,
+}
+
+impl Worker {
+    fn offer(&mut self, p: &Puzzle, chain: &Arc<Chain>) {
+        let h = heuristic(p);
+        if self.best.as_ref().is_none_or(|(best, _)| h < *best) {
+            self.best = Some((h, chain.clone()));
+        }
+    }
+
+    fn merge(workers: impl Iterator<Item = Self>, search: &mut Search) -> Option<Arc<Chain>> {
+        let mut deepest: Option<Arc<Chain>> = None;
+        for w in workers {
+            if let Some((h, chain)) = w.best {
+                search.consider(h, || chain.moves());
+            }
+            search.depth_limited |= w.depth_limited;
+            search.generate(w.generated);
+            search.duplicates += w.duplicates;
+            if let Some(d) = w
+                .deepest
+                .filter(|d| deepest.as_ref().is_none_or(|c| d.depth > c.depth))
+            {
+                deepest = Some(d);
+            }
+        }
+        deepest
+    }
+}
+
+fn parallel_dfs(
+    roots: Vec<Task>,
+    threads: usize,
+    search: &mut Search,
+) -> Result<ValidMoves, NoSolution> {
+    let queues: Vec<Mutex<VecDeque<Task>>> =
+        (0..threads).map(|_| Mutex::new(VecDeque::new())).collect();
+    let pending = AtomicUsize::new(roots.len());
+    for (i, task) in roots.into_iter().enumerate() {
+        queues[i % threads].lock().unwrap().push_back(task);
+    }
+    // Sharded so threads rarely wait on each other's inserts
+    let hasher = RandomState::new();
+    let visited: Vec<Mutex<HashSet<Packed>>> = (0..threads * 4)
+        .map(|_| Mutex::new(HashSet::new()))
+        .collect();
+    let expanded = AtomicUsize::new(search.expanded);
+    let depth = AtomicUsize::new(search.depth);
+    let done = AtomicBool::new(false);
+    let stopped = Mutex::new(None);
+    let solution = Mutex::new(None);
+
+    let (budget, started, plan) = (search.budget.clone(), search.started, search.plan);
+    let workers: Vec<Worker> = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|i| {
+                let (queues, pending, done) = (&queues, &pending, &done);
+                let (hasher, visited, expanded, depth) = (&hasher, &visited, &expanded, &depth);
+                let (budget, stopped, solution) = (&budget, &stopped, &solution);
+                s.spawn(move || {
+                    let mut worker = Worker::default();
+                    while !done.load(AtomicOrdering::Relaxed) {
+                        let task = queues[i].lock().unwrap().pop_back().or_else(|| {
+                            (1..threads)
+                                .find_map(|j| queues[(i + j) % threads].lock().unwrap().pop_front())
+                        });
+                        let Some((packed, chain)) = task else {
+                            if pending.load(AtomicOrdering::Acquire) == 0 {
+                                break;
+                            }
+                            thread::yield_now();
+                            continue;
+                        };
+
+                        let puzzle = Puzzle::from(&packed);
+                        if puzzle.is_solved() {
+                            *solution.lock().unwrap() = Some(Ok(chain.moves()));
+                            done.store(true, AtomicOrdering::Relaxed);
+                            break;
+                        }
+                        if puzzle.has_unknown() {
+                            let uncovered = NoSolution::HasUnknown(puzzle, chain.moves());
+                            *solution.lock().unwrap() = Some(Err(uncovered));
+                            done.store(true, AtomicOrdering::Relaxed);
+                            break;
+                        }
+                        if let Some(rest) = plan.and_then(|plan| plan.rest.get(&packed)) {
+                            let mut moves = chain.moves();
+                            moves.0.extend_from_slice(rest);
+                            *solution.lock().unwrap() = Some(Ok(moves));
+                            done.store(true, AtomicOrdering::Relaxed);
+                            break;
+                        }
+                        let key = packed.canonical();
+                        let shard = hasher.hash_one(&key) as usize % visited.len();
+                        if !visited[shard].lock().unwrap().insert(key) {
+                            worker.duplicates += 1;
+                        } else {
+                            let n = expanded.fetch_add(1, AtomicOrdering::Relaxed);
+                            if let Err(limit) = budget.check(started, n) {
+                                *stopped.lock().unwrap() = Some(limit);
+                                done.store(true, AtomicOrdering::Relaxed);
+                                break;
+                            }
+                            depth.fetch_max(chain.depth + 1, AtomicOrdering::Relaxed);
+                            worker.offer(&puzzle, &chain);
+
+                            let next = if budget.beyond(chain.depth + 1) {
+                                worker.depth_limited = true;
+                                vec![]
+                            } else {
+                                children(&puzzle, Some(&chain))
+                            };
+                            worker.generated += next.len();
+                            if next.is_empty()
+                                && !worker.depth_limited
+                                && worker
+                                    .deepest
+                                    .as_ref()
+                                    .is_none_or(|d| chain.depth > d.depth)
+                            {
+                                worker.deepest = Some(chain);
+                            }
+                            // Counted before this task is finished so
+                            // the total can't touch zero in between
+                            pending.fetch_add(next.len(), AtomicOrdering::AcqRel);
+                            queues[i].lock().unwrap().extend(next);
+                        }
+                        pending.fetch_sub(1, AtomicOrdering::AcqRel);
+                    }
+                    worker
+                })
+            })
+            .collect();
+        if search.observer.is_some() {
+            while workers.iter().any(|w| !w.is_finished()) {
+                thread::sleep(Duration::from_millis(10));
+                search.expanded = expanded.load(AtomicOrdering::Relaxed);
+                search.depth = depth.load(AtomicOrdering::Relaxed);
+                let seen = visited.iter().map(|v| v.lock().unwrap().len()).sum();
+                search.report(pending.load(AtomicOrdering::Relaxed), seen);
+            }
+        }
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+
+    search.expanded = expanded.into_inner();
+    search.depth = depth.into_inner();
+    search.peak_visited = visited.iter().map(|v| v.lock().unwrap().len()).sum();
+    let deepest = Worker::merge(workers.into_iter(), search);
+    if let Some(found) = solution.into_inner().unwrap() {
+        return found;
+    }
+    if let Some(limit) = stopped.into_inner().unwrap() {
+        return Err(search.out_of_budget(limit));
+    }
+    Err(match deepest {
+        Some(c) => search.exhausted(c.moves(), c.depth),
+        None => search.exhausted(ValidMoves(vec![]), 0),
+    })
+}
+
+/// Whether [`parallel_bfs`] ends at `p`, either solved
+/// or with something hidden uncovered
+fn stops_at(p: &Packed) -> bool {
+    let p = Puzzle::from(p);
+    p.is_solved() || p.has_unknown()
+}
+
+fn parallel_bfs(
+    p: &Puzzle,
+    roots: Vec<Task>,
+    threads: usize,
+    search: &mut Search,
+) -> Result<ValidMoves, NoSolution> {
+    // By the moves it took to first reach each state
+    let mut visited = HashMap::<Packed, usize>::new();
+    visited.insert(Packed::from(p).canonical(), 0);
+
+    let mut candidates: Vec<(Task, bool)> = roots
+        .into_iter()
+        .map(|t| {
+            let stops = stops_at(&t.0);
+            (t, stops)
+        })
+        .collect();
+    let mut deepest = None;
+    let mut shortcut = None;
+    loop {
+        // Merging in frontier order keeps the outcome independent of
+        // how the work was split between threads
+        let mut frontier = vec![];
+        for (task, stops) in candidates {
+            let moves = task.1.depth + 1;
+            if visited.insert(task.0.canonical(), moves).is_some() {
+                search.prune();
+                continue;
+            }
+            if stops {
+                let puzzle = Puzzle::from(&task.0);
+                if puzzle.has_unknown() {
+                    return Err(NoSolution::HasUnknown(puzzle, task.1.moves()));
+                }
+                search.settle(moves, visited);
+                return Ok(task.1.moves());
+            }
+            search.shortcut(&task.0, &mut shortcut, || task.1.moves());
+            frontier.push(task);
+        }
+        let Some((_, chain)) = frontier.first() else {
+            break;
+        };
+        // The next level is a move further, so nothing shorter is left
+        if let Some(moves) = shortcut.take_if(|s| s.0.len() <= chain.depth + 2) {
+            search.settle(moves.0.len(), visited);
+            return Ok(moves);
+        }
+        deepest = Some(chain.clone());
+        if search.too_deep(chain.depth + 1) {
+            break;
+        }
+        // Checked a level at a time, since stopping halfway through
+        // one would depend on how quickly each thread got through it
+        if let Err(limit) = search.budget.check(search.started, search.expanded) {
+            return Err(search.out_of_budget(limit));
+        }
+        search.expanded += frontier.len();
+        search.depth = chain.depth + 1;
+        search.peak_visited = visited.len();
+        search.report(frontier.len(), visited.len());
+
+        let chunk = frontier.len().div_ceil(threads);
+        let expanded: Vec<(Vec<(Task, bool)>, Worker)> = thread::scope(|s| {
+            let workers: Vec<_> = frontier
+                .chunks(chunk)
+                .map(|tasks| {
+                    s.spawn(move || {
+                        let mut worker = Worker::default();
+                        let mut next = vec![];
+                        for (packed, chain) in tasks {
+                            let puzzle = Puzzle::from(packed);
+                            worker.offer(&puzzle, chain);
+                            let kids = children(&puzzle, Some(chain));
+                            worker.generated += kids.len();
+                            for t in kids {
+                                let stops = stops_at(&t.0);
+                                next.push((t, stops));
+                            }
+                        }
+                        (next, worker)
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        let (next, workers): (Vec<_>, Vec<_>) = expanded.into_iter().unzip();
+        Worker::merge(workers.into_iter(), search);
+        candidates = next.into_iter().flatten().collect();
+    }
+
+    if let Some(moves) = shortcut {
+        return Ok(moves);
+    }
+    Err(match deepest {
+        Some(c) => search.exhausted(c.moves(), c.depth),
+        None => search.exhausted(ValidMoves(vec![]), 0),
+    })
+}
+
+fn get_move_chain(arena: &Arena<PuzzleState>, id: NodeId) -> ValidMoves {
+    let mut moves: Vec<(u8, u8)> = id
+        .ancestors(arena)
+        .map(|d| arena[d].get().played_move)
+        .collect();
+    moves.reverse();
+    ValidMoves(moves)
+}
+
+#[cfg(test)]
+mod solve_test {
+    use crate::fixtures::{replay, solved, test_puzzle};
+    use crate::puzzle::{Invalid, Puzzle, ValidMoves};
+    use crate::solve::{
+        all_solutions, astar_puzzle, bfs_puzzle, dfs_puzzle, extra_tubes, greedy_puzzle, heuristic,
+        idastar_puzzle, parallel_puzzle, reveal_puzzle, strategy, Budget, Cancel, Limit, MoveOrder,
+        NoSolution, Parallel, Plan, Reveal, Search, Stats, STRATEGIES,
+    };
+    use crate::state::State::{Empty, Unknown, Water};
+    use crate::tube::Pour;
+    use crate::water::{BLUE, GREEN, RED};
+    use std::time::Duration;
+
+    #[test]
+    fn test_solve() {
+        let mut p = Puzzle::new(5);
+        p.set_whole_tube(0, [Water(GREEN), Water(BLUE), Water(RED), Water(GREEN)]);
+        p.set_whole_tube(1, [Water(BLUE), Water(BLUE), Water(RED), Water(GREEN)]);
+        p.set_whole_tube(2, [Water(BLUE), Water(RED), Water(RED), Water(GREEN)]);
+
+        assert!(dfs_puzzle(&p, MoveOrder::default(), &mut Search::default()).is_ok());
+    }
+
+    #[test]
+    fn test_solve_invalid() {
+        let mut p = test_puzzle();
+        p.set_tube(0, 0, Water(RED));
+        let Err(NoSolution::Invalid(Invalid::ColourCount(GREEN, 3, 4))) =
+            dfs_puzzle(&p, MoveOrder::default(), &mut Search::default())
+        else {
+            panic!("five reds should be caught before searching");
+        };
+    }
+
+    #[test]
+    fn test_solve_planned() {
+        let p = test_puzzle();
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        let Some(plan) = Plan::new(&p, &optimal) else {
+            panic!("a solution is a plan");
+        };
+        assert_eq!(plan.rest(&p).map(|m| m.0), Some(optimal.0.clone()));
+        let next = replay(&p, &optimal.0[..1]);
+        assert_eq!(plan.rest(&next).map(|m| m.0), Some(optimal.0[1..].to_vec()));
+
+        // Going another way first still ends up following the plan
+        for m in p.valid_moves().get() {
+            let deviated = replay(&p, &[m]);
+            let mut search = Search::default().towards(&plan);
+            let Ok(moves) = dfs_puzzle(&deviated, MoveOrder::default(), &mut search) else {
+                continue;
+            };
+            assert!(replay(&deviated, &moves.0).is_solved());
+        }
+    }
+
+    #[test]
+    fn test_solve_explored() {
+        let p = test_puzzle();
+        let mut search = Search::default();
+        let optimal = solved(bfs_puzzle(&p, &mut search));
+        let Some(plan) = Plan::new(&p, &optimal) else {
+            panic!("a solution is a plan");
+        };
+        let plan = plan.explored(search.take_explored());
+        assert!(plan.explored.is_some());
+
+        // The solution is optimal, so the bound is exact along it
+        let search = Search::default().towards(&plan);
+        for i in 0..optimal.0.len() {
+            let on_the_way = replay(&p, &optimal.0[..i]);
+            assert_eq!(search.estimate(&on_the_way), optimal.0.len() - i);
+        }
+
+        // Searches that find the fewest moves still do with the plan
+        let optimal = [
+            ("bfs", &[][..]),
+            ("astar", &[]),
+            ("idastar", &[]),
+            ("parallel", &["2", "det"]),
+        ];
+        for m in p.valid_moves().get() {
+            let deviated = replay(&p, &[m]);
+            let Ok(fewest) = bfs_puzzle(&deviated, &mut Search::default()) else {
+                continue;
+            };
+            for (name, args) in optimal {
+                let Some(solver) = strategy(name).and_then(|s| (s.build)(args)) else {
+                    panic!("{name} should build");
+                };
+                let mut search = Search::default().towards(&plan);
+                let Ok(moves) = solver.solve(&deviated, &mut search) else {
+                    panic!("{name} should solve {deviated:?}");
+                };
+                assert!(replay(&deviated, &moves.0).is_solved());
+                assert_eq!(moves.0.len(), fewest.0.len(), "{name}");
+            }
+            for name in ["greedy", "parallel"] {
+                let mut search = Search::default().towards(&plan);
+                let solver = (strategy(name).unwrap().build)(&[]).unwrap();
+                let Ok(moves) = solver.solve(&deviated, &mut search) else {
+                    panic!("{name} should solve {deviated:?}");
+                };
+                assert!(replay(&deviated, &moves.0).is_solved());
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_unsolved() {
+        let mut p = Puzzle::new(5);
+        p.set_whole_tube(0, [Empty, Water(RED), Water(BLUE), Unknown]);
+        p.set_whole_tube(1, [Empty, Water(RED), Water(RED), Water(RED)]);
+        p.set_whole_tube(2, [Empty, Water(BLUE), Water(BLUE), Water(BLUE)]);
+        p.set_whole_tube(3, [Empty, Water(GREEN), Unknown, Unknown]);
+        let Ok(reveal) = reveal_puzzle(&p, Reveal::Most, &mut Search::default()) else {
+            panic!("cells can be uncovered");
+        };
+        // Uncovering cells doesn't solve anything, so there's nothing to follow
+        assert!(Plan::new(&p, &reveal).is_none());
+        assert!(Plan::new(&p, &ValidMoves(vec![])).is_none());
+    }
+
+    fn hidden_puzzle() -> Puzzle {
+        let mut p = Puzzle::new(5);
+        p.set_whole_tube(0, [Empty, Water(RED), Unknown, Unknown]);
+        p.set_whole_tube(1, [Empty, Empty, Empty, Water(RED)]);
+        p.set_whole_tube(2, [Water(BLUE), Water(BLUE), Water(BLUE), Unknown]);
+        p
+    }
+
+    #[test]
+    fn test_solve_hidden() {
+        let p = hidden_puzzle();
+        let Err(NoSolution::HasUnknown(uncovered, moves)) =
+            dfs_puzzle(&p, MoveOrder::default(), &mut Search::default())
+        else {
+            panic!("search should stop once something hidden is uncovered");
+        };
+        assert!(!moves.0.is_empty());
+        assert_eq!(replay(&p, &moves.0), uncovered);
+    }
+
+    #[test]
+    fn test_solve_hidden_bfs() {
+        let p = hidden_puzzle();
+        let Err(NoSolution::HasUnknown(uncovered, moves)) = bfs_puzzle(&p, &mut Search::default())
+        else {
+            panic!("search should stop once something hidden is uncovered");
+        };
+        // Pouring the red away is the quickest way to uncover anything
+        assert_eq!(moves.0.len(), 1);
+        assert_eq!(replay(&p, &moves.0), uncovered);
+    }
+
+    #[test]
+    fn test_solve_hidden_informed() {
+        let p = hidden_puzzle();
+        for (name, solve) in [
+            ("astar", astar_puzzle as fn(&Puzzle, &mut Search) -> _),
+            ("greedy", greedy_puzzle),
+            ("idastar", idastar_puzzle),
+        ] {
+            let Err(NoSolution::HasUnknown(uncovered, moves)) = solve(&p, &mut Search::default())
+            else {
+                panic!("{name} should stop once something hidden is uncovered");
+            };
+            assert!(!moves.0.is_empty(), "{name}");
+            assert_eq!(replay(&p, &moves.0), uncovered, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_solve_hidden_parallel() {
+        let p = hidden_puzzle();
+        for deterministic in [false, true] {
+            let options = Parallel {
+                threads: 2,
+                deterministic,
+            };
+            let Err(NoSolution::HasUnknown(uncovered, moves)) =
+                parallel_puzzle(&p, &options, &mut Search::default())
+            else {
+                panic!("search should stop once something hidden is uncovered");
+            };
+            assert!(!moves.0.is_empty());
+            assert_eq!(replay(&p, &moves.0), uncovered);
+        }
+    }
+
+    #[test]
+    fn test_reveal() {
+        let p = hidden_puzzle();
+        let Ok(next) = reveal_puzzle(&p, Reveal::Next, &mut Search::default()) else {
+            panic!("pouring red away uncovers tube 0");
+        };
+        assert_eq!(next.0.len(), 1);
+        assert!(replay(&p, &next.0).has_unknown());
+
+        let Ok(most) = reveal_puzzle(&p, Reveal::Most, &mut Search::default()) else {
+            panic!("both tubes can be uncovered");
+        };
+        let after = replay(&p, &most.0);
+        assert_eq!(most.0.len(), 2);
+        assert_eq!(after.tubes()[0].top(), Unknown);
+        assert_eq!(after.tubes()[2].top(), Unknown);
+
+        // Running out of nodes after the first reveal is not the most
+        let budget = Budget {
+            max_nodes: Some(2),
+            ..Budget::default()
+        };
+        let Err(NoSolution::OutOfBudget(closest, Limit::Nodes)) =
+            reveal_puzzle(&p, Reveal::Most, &mut Search::new(budget))
+        else {
+            panic!("node limit should stop the search");
+        };
+        assert!(replay(&p, &closest.0).has_unknown());
+
+        // Either pour uncovers something, but leaves nothing to pour
+        let mut stuck = Puzzle::from(vec![crate::tube::Tube::empty(); 3]);
+        stuck.set_whole_tube(0, [Empty, Water(RED), Unknown, Unknown]);
+        stuck.set_whole_tube(1, [Empty, Empty, Water(RED), Unknown]);
+        stuck.set_whole_tube(2, [Empty, Unknown, Unknown, Unknown]);
+        assert!(matches!(
+            reveal_puzzle(&stuck, Reveal::Next, &mut Search::default()),
+            Err(NoSolution::NothingToReveal)
+        ));
+    }
+
+    #[test]
+    fn test_move_order() {
+        let mut p = Puzzle::new(5);
+        p.set_whole_tube(0, [Empty, Water(RED), Water(RED), Water(RED)]);
+        p.set_whole_tube(1, [Empty, Empty, Water(RED), Water(BLUE)]);
+        p.set_whole_tube(2, [Empty, Empty, Water(BLUE), Water(BLUE)]);
+        p.set_whole_tube(3, [Empty, Empty, Water(BLUE), Water(GREEN)]);
+        let raw = vec![(1, 0), (1, 4), (2, 3), (3, 2), (3, 4)];
+
+        let mut moves = p.valid_moves().get();
+        assert_eq!(moves, raw);
+        MoveOrder::NONE.sort(&p, &mut moves);
+        assert_eq!(moves, raw);
+        // The stack pops from the end: filling tube 0 first, then
+        // pouring onto blue without breaking up tube 2
+        MoveOrder::default().sort(&p, &mut moves);
+        assert_eq!(moves, vec![(1, 4), (3, 4), (2, 3), (3, 2), (1, 0)]);
+    }
+
+    #[test]
+    fn test_solve_optimal() {
+        let p = test_puzzle();
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        let dfs = solved(dfs_puzzle(&p, MoveOrder::default(), &mut Search::default()));
+
+        assert!(replay(&p, &optimal.0).is_solved());
+        assert!(optimal.0.len() <= dfs.0.len());
+    }
+
+    #[test]
+    fn test_heuristic_shared_colour() {
+        // Red and blue each fill two tubes
+        let mut p = Puzzle::with_capacity(6, 2);
+        p.set_whole_tube(0, [Water(RED), Water(BLUE)]);
+        p.set_whole_tube(1, [Water(BLUE), Water(RED)]);
+        p.set_whole_tube(2, [Water(RED), Water(BLUE)]);
+        p.set_whole_tube(3, [Water(BLUE), Water(RED)]);
+        assert_eq!(p.validate(), Ok(()));
+
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        let astar = solved(astar_puzzle(&p, &mut Search::default()));
+        assert!(heuristic(&p) <= optimal.0.len());
+        assert_eq!(heuristic(&replay(&p, &optimal.0)), 0);
+        assert_eq!(astar.0.len(), optimal.0.len());
+    }
+
+    #[test]
+    fn test_solve_informed() {
+        let p = test_puzzle();
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        let astar = solved(astar_puzzle(&p, &mut Search::default()));
+        let idastar = solved(idastar_puzzle(&p, &mut Search::default()));
+
+        assert!(heuristic(&p) <= optimal.0.len());
+        assert!(replay(&p, &astar.0).is_solved());
+        assert!(replay(&p, &idastar.0).is_solved());
+        assert_eq!(astar.0.len(), optimal.0.len());
+        assert_eq!(idastar.0.len(), optimal.0.len());
+    }
+
+    #[test]
+    fn test_solve_parallel() {
+        let p = test_puzzle();
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        let parallel = solved(parallel_puzzle(
+            &p,
+            &Parallel::default(),
+            &mut Search::default(),
+        ));
+        assert!(replay(&p, &parallel.0).is_solved());
+
+        let solve = |threads| {
+            let options = Parallel {
+                threads,
+                deterministic: true,
+            };
+            parallel_puzzle(&p, &options, &mut Search::default())
+                .ok()
+                .map(|m| m.get())
+        };
+        let single = solve(1);
+        assert_eq!(single.as_ref().map(Vec::len), Some(optimal.0.len()));
+        assert_eq!(solve(4), single);
+    }
+
+    #[test]
+    fn test_all_solutions() {
+        // Red fills two tubes, so it rests at the bottom of both
+        let mut shared = Puzzle::with_capacity(5, 3);
+        shared.set_whole_tube(0, [Water(RED), Water(BLUE), Water(RED)]);
+        shared.set_whole_tube(1, [Water(BLUE), Water(RED), Water(RED)]);
+        shared.set_whole_tube(2, [Water(RED), Water(RED), Water(BLUE)]);
+        assert_eq!(shared.validate(), Ok(()));
+
+        for p in [test_puzzle(), shared] {
+            let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+            let all = solved(all_solutions(&p, None, &mut Search::default()));
+            assert_eq!(all.length, optimal.0.len());
+            assert!(!all.capped && all.stopped.is_none());
+            assert!(!all.found.is_empty());
+            for (i, moves) in all.found.iter().enumerate() {
+                assert_eq!(moves.0.len(), all.length);
+                assert!(replay(&p, &moves.0).is_solved());
+                assert!(all.found[..i].iter().all(|m| m.0 != moves.0));
+            }
+
+            let one = solved(all_solutions(&p, Some(1), &mut Search::default()));
+            assert_eq!(one.found.len(), 1);
+            assert!(one.capped || all.found.len() == 1);
+        }
+    }
+
+    #[test]
+    fn test_solve_greedy() {
+        let p = test_puzzle();
+        let greedy = solved(greedy_puzzle(&p, &mut Search::default()));
+        assert!(replay(&p, &greedy.0).is_solved());
+    }
+
+    #[test]
+    fn test_strategies() {
+        let p = test_puzzle();
+        for s in STRATEGIES {
+            for name in s.aliases.iter().chain([&s.name]) {
+                assert_eq!(strategy(name).map(|t| t.name), Some(s.name));
+            }
+            let Some(solver) = (s.build)(&[]) else {
+                panic!("{} should build without arguments", s.name);
+            };
+            let moves = match solver.solve(&p, &mut Search::default()) {
+                Ok(moves) => moves,
+                // Only plans pours while something is hidden
+                Err(NoSolution::NothingToReveal) if s.name == "reveal" => continue,
+                Err(_) => panic!("{} should solve the puzzle", s.name),
+            };
+            assert!(replay(&p, &moves.0).is_solved(), "{}", s.name);
+        }
+        assert!(strategy("nope").is_none());
+        assert!((strategy("dfs").unwrap().build)(&["4"]).is_none());
+        assert!((strategy("parallel").unwrap().build)(&["2", "det"]).is_some());
+        assert!((strategy("parallel").unwrap().build)(&["0"]).is_none());
+    }
+
+    #[test]
+    fn test_solve_budget() {
+        let p = test_puzzle();
+        let budget = Budget {
+            max_nodes: Some(3),
+            ..Budget::default()
+        };
+        let Err(NoSolution::OutOfBudget(moves, Limit::Nodes)) =
+            bfs_puzzle(&p, &mut Search::new(budget))
+        else {
+            panic!("node limit should stop the search");
+        };
+        replay(&p, &moves.0);
+
+        let budget = Budget {
+            max_depth: Some(3),
+            ..Budget::default()
+        };
+        assert!(matches!(
+            astar_puzzle(&p, &mut Search::new(budget.clone())),
+            Err(NoSolution::OutOfBudget(_, Limit::Depth))
+        ));
+        assert!(matches!(
+            idastar_puzzle(&p, &mut Search::new(budget)),
+            Err(NoSolution::OutOfBudget(_, Limit::Depth))
+        ));
+
+        let cancel = Cancel::default();
+        cancel.cancel();
+        let budget = Budget {
+            cancel: Some(cancel),
+            ..Budget::default()
+        };
+        assert!(matches!(
+            parallel_puzzle(&p, &Parallel::default(), &mut Search::new(budget)),
+            Err(NoSolution::OutOfBudget(_, Limit::Cancelled))
+        ));
+    }
+
+    #[test]
+    fn test_solve_progress() {
+        let p = test_puzzle();
+        let mut reports = vec![];
+        let mut search = Search::default().observe(Duration::ZERO, |p| reports.push(*p));
+        assert!(bfs_puzzle(&p, &mut search).is_ok());
+        drop(search);
+
+        assert!(!reports.is_empty());
+        assert!(reports.windows(2).all(|w| w[0].expanded < w[1].expanded));
+        assert!(reports.iter().all(|r| r.visited > 0 && r.depth > 0));
+    }
+
+    #[test]
+    fn test_solve_stats() {
+        let p = test_puzzle();
+        let mut search = Search::default();
+        let moves = solved(astar_puzzle(&p, &mut search));
+        let stats = search.stats();
+
+        assert!(stats.expanded > 0);
+        assert!(stats.generated >= stats.expanded);
+        assert!(stats.peak_visited > 0 && stats.visited_bytes > 0);
+        assert_eq!(stats.max_depth, moves.0.len() - 1);
+        assert!(stats.branching_factor() > 1.0);
+    }
+
+    #[test]
+    fn test_branching_factor() {
+        let stats = Stats {
+            generated: 2 + 4 + 8,
+            max_depth: 3,
+            ..Stats::default()
+        };
+        assert!((stats.branching_factor() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_solve_capacity() {
+        let mut small = Puzzle::with_capacity(4, 3);
+        small.set_whole_tube(0, [Water(RED), Water(BLUE), Water(RED)]);
+        small.set_whole_tube(1, [Water(BLUE), Water(RED), Water(BLUE)]);
+        let mut big = Puzzle::with_capacity(4, 6);
+        big.set_whole_tube(
+            0,
+            [
+                Water(GREEN),
+                Water(RED),
+                Water(GREEN),
+                Water(RED),
+                Water(GREEN),
+                Water(RED),
+            ],
+        );
+        big.set_whole_tube(
+            1,
+            [
+                Water(RED),
+                Water(GREEN),
+                Water(RED),
+                Water(GREEN),
+                Water(RED),
+                Water(GREEN),
+            ],
+        );
+
+        for p in [small, big] {
+            assert_eq!(p.validate(), Ok(()));
+            let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+            assert!(replay(&p, &optimal.0).is_solved());
+            assert!(heuristic(&p) <= optimal.0.len());
+            let moves = solved(dfs_puzzle(&p, MoveOrder::default(), &mut Search::default()));
+            assert!(replay(&p, &moves.0).is_solved());
+        }
+    }
+
+    #[test]
+    fn test_solve_sticky() {
+        let mut p = test_puzzle();
+        p.set_whole_tube(3, [Empty, Empty, Empty, Water(GREEN)]);
+        p.set_tube(0, 0, Empty);
+        p.set_sticky(3, true);
+        assert_eq!(p.validate(), Ok(()));
+
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        assert!(optimal.0.iter().all(|m| m.0 != 3));
+        assert!(replay(&p, &optimal.0).is_solved());
+        let moves = solved(dfs_puzzle(&p, MoveOrder::default(), &mut Search::default()));
+        assert!(replay(&p, &moves.0).is_solved());
+    }
+
+    #[test]
+    fn test_solve_overflow() {
+        let p = test_puzzle();
+        let whole = solved(bfs_puzzle(&p, &mut Search::default()));
+        let mut p = p;
+        p.set_pour_rule(Pour::Overflow);
+        // Every whole pour is still allowed, so it can only get shorter
+        let overflow = solved(bfs_puzzle(&p, &mut Search::default()));
+        assert!(overflow.0.len() <= whole.0.len());
+        assert!(replay(&p, &overflow.0).is_solved());
+        let optimal = solved(astar_puzzle(&p, &mut Search::default()));
+        assert_eq!(optimal.0.len(), overflow.0.len());
+        let moves = solved(dfs_puzzle(&p, MoveOrder::default(), &mut Search::default()));
+        assert!(replay(&p, &moves.0).is_solved());
+    }
+
+    #[test]
+    fn test_solve_single() {
+        let mut p = test_puzzle();
+        p.set_pour_rule(Pour::Single);
+        let optimal = solved(bfs_puzzle(&p, &mut Search::default()));
+        let mut played = p.clone();
+        for m in &optimal.0 {
+            let before = played.tubes()[m.0 as usize].num_free();
+            played.pour(m.0.into(), m.1.into()).unwrap();
+            assert_eq!(played.tubes()[m.0 as usize].num_free(), before + 1);
+        }
+        assert!(played.is_solved());
+        let astar = solved(astar_puzzle(&p, &mut Search::default()));
+        assert_eq!(astar.0.len(), optimal.0.len());
+        let moves = solved(dfs_puzzle(&p, MoveOrder::default(), &mut Search::default()));
+        assert!(replay(&p, &moves.0).is_solved());
+    }
+
+    #[test]
+    fn test_extra_tubes() {
+        use crate::water::YELLOW;
+        let mut p = Puzzle::from(vec![crate::tube::Tube::empty(); 5]);
+        for tube in 0..4 {
+            p.set_whole_tube(tube, [Water(RED), Water(GREEN), Water(BLUE), Water(YELLOW)]);
+        }
+        let Err(NoSolution::CannotBeSolved(..)) =
+            extra_tubes(&p, 0, &bfs_puzzle, &mut Search::default())
+        else {
+            panic!("one empty tube shouldn't be enough");
+        };
+
+        let Ok((bigger, moves)) = extra_tubes(&p, 3, &bfs_puzzle, &mut Search::default()) else {
+            panic!("puzzle should be solvable with another tube");
+        };
+        assert_eq!(bigger.size(), 6);
+        assert!(bigger.tubes()[5].is_empty());
+        assert!(replay(&bigger, &moves.0).is_solved());
 
-        assert!(matches!(dfs_puzzle(&p), Ok(_)));
+        // Already solvable, so nothing is added
+        let (same, _) = solved(extra_tubes(
+            &test_puzzle(),
+            3,
+            &bfs_puzzle,
+            &mut Search::default(),
+        ));
+        assert_eq!(same, test_puzzle());
     }
 }
@@ -1,5 +1,15 @@
 #[derive(
-    Default, Clone, Copy, Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq, Hash,
+    Default,
+    Clone,
+    Copy,
+    Debug,
+    serde::Serialize,
+    serde::Deserialize,
+    PartialEq,
+    Eq,
+    Ord,
+    PartialOrd,
+    Hash,
 )]
 pub enum State {
     #[default]
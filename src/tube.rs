@@ -1,25 +1,173 @@
 use crate::state::State;
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+/// Cells in a tube unless the puzzle says otherwise
+pub const DEFAULT_CAPACITY: usize = 4;
+/// Most cells a tube can hold
+pub const MAX_CAPACITY: usize = 8;
+
+/// How much a pour moves
+#[derive(
+    Default, Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash,
+)]
+pub enum Pour {
+    /// The whole run on top, which can't be
+    /// poured if it doesn't all fit
+    #[default]
+    Whole,
+    /// As much of the run on top as fits,
+    /// and the rest stays behind
+    Overflow,
+    /// A single cell, like the balls in ball sort
+    Single,
+}
+
+impl Pour {
+    pub const ALL: [Self; 3] = [Self::Whole, Self::Overflow, Self::Single];
+}
+
+impl std::fmt::Display for Pour {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Whole => write!(f, "whole"),
+            Self::Overflow => write!(f, "overflow"),
+            Self::Single => write!(f, "single"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Pour {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|p| p.to_string() == value.to_lowercase())
+            .ok_or_else(|| format!("Unknown pour rule: {value}"))
+    }
+}
+
+/// Cells from the top of the tube down. Only the first
+/// `capacity` are used, the rest are always empty.
+///
+/// A sticky tube is locked: it can be poured into but not from,
+/// until it is filled with a single colour and unlocks.
+///
+/// Every tube of a puzzle follows the puzzle's [`Pour`] rule.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(try_from = "SavedTube", into = "SavedTube")]
 pub struct Tube {
-    t: [State; 4],
+    t: [State; MAX_CAPACITY],
+    capacity: u8,
     sticky: bool,
+    pour: Pour,
+}
+
+/// How a tube is saved, with only as many cells as it holds
+#[derive(Serialize, Deserialize)]
+struct SavedTube {
+    t: Vec<State>,
+    sticky: bool,
+}
+
+impl TryFrom<SavedTube> for Tube {
+    type Error = String;
+
+    fn try_from(saved: SavedTube) -> Result<Self, Self::Error> {
+        let capacity = saved.t.len();
+        if !(1..=MAX_CAPACITY).contains(&capacity) {
+            return Err(format!(
+                "a tube holds {capacity} cells, it must hold between 1 and {MAX_CAPACITY}"
+            ));
+        }
+        let mut tube = Self::with_capacity(capacity);
+        tube.set_tube(&saved.t);
+        tube.sticky = saved.sticky;
+        Ok(tube)
+    }
+}
+
+impl From<Tube> for SavedTube {
+    fn from(tube: Tube) -> Self {
+        Self {
+            t: tube.cells().to_vec(),
+            sticky: tube.sticky,
+        }
+    }
+}
+
+/// A tube of unknown cells
+impl Default for Tube {
+    fn default() -> Self {
+        Self::unknown(DEFAULT_CAPACITY)
+    }
 }
 
 impl Tube {
+    #[cfg(test)]
     pub const fn empty() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// An empty tube holding `capacity` cells
+    pub const fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity >= 1 && capacity <= MAX_CAPACITY);
         Self {
-            t: [State::Empty, State::Empty, State::Empty, State::Empty],
+            t: [State::Empty; MAX_CAPACITY],
+            capacity: capacity as u8,
             sticky: false,
+            pour: Pour::Whole,
         }
     }
 
-    pub fn set_tube(&mut self, t: [State; 4]) {
-        self.t = t;
+    /// A tube of `capacity` unknown cells
+    pub fn unknown(capacity: usize) -> Self {
+        let mut tube = Self::with_capacity(capacity);
+        tube.t[..capacity].fill(State::Unknown);
+        tube
+    }
+
+    pub const fn capacity(self) -> usize {
+        self.capacity as usize
+    }
+
+    /// The cells in use, from the top down
+    pub fn cells(&self) -> &[State] {
+        &self.t[..self.capacity()]
+    }
+
+    pub fn set_tube(&mut self, t: &[State]) {
+        assert_eq!(
+            t.len(),
+            self.capacity(),
+            "tube holds {} cells",
+            self.capacity()
+        );
+        self.t[..t.len()].copy_from_slice(t);
+    }
+
+    pub const fn is_sticky(self) -> bool {
+        self.sticky
+    }
+
+    pub fn set_sticky(&mut self, sticky: bool) {
+        self.sticky = sticky;
+    }
+
+    pub const fn pour_rule(self) -> Pour {
+        self.pour
+    }
+
+    pub fn set_pour_rule(&mut self, pour: Pour) {
+        self.pour = pour;
     }
 
     pub fn set(&mut self, idx: usize, state: State) {
+        assert!(
+            idx < self.capacity(),
+            "tube holds {} cells",
+            self.capacity()
+        );
         self.t[idx] = state;
     }
 
@@ -27,10 +175,19 @@ impl Tube {
         self.t[idx]
     }
 
+    /// Whether the tube is full of one colour
+    pub fn is_complete(self) -> bool {
+        self.num_to_pour() == self.capacity()
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.num_free() == self.capacity()
+    }
+
     pub fn num_to_pour(self) -> usize {
         if let State::Water(w) = self.top() {
             let mut to_pour = 1;
-            for i in (self.num_free() + 1)..4 {
+            for i in (self.num_free() + 1)..self.capacity() {
                 if matches!(self.t[i], State::Water(other) if other == w) {
                     to_pour += 1;
                 } else {
@@ -44,14 +201,11 @@ impl Tube {
     }
 
     pub const fn num_free(self) -> usize {
-        use State::Empty as E;
-        match (self.t[0], self.t[1], self.t[2], self.t[3]) {
-            (E, E, E, E) => 4,
-            (E, E, E, _) => 3,
-            (E, E, _, _) => 2,
-            (E, _, _, _) => 1,
-            (_, _, _, _) => 0,
+        let mut free = 0;
+        while free < self.capacity() && matches!(self.t[free], State::Empty) {
+            free += 1;
         }
+        free
     }
 
     pub fn cannot_pour_to(self, other: Self) -> bool {
@@ -59,6 +213,9 @@ impl Tube {
     }
 
     pub fn can_pour_to(self, other: Self) -> bool {
+        if self.sticky {
+            return false;
+        }
         match (self.top(), other.top()) {
             (State::Unknown | State::Empty, _) | (_, State::Unknown) => false,
             (_, State::Empty) => {
@@ -68,55 +225,138 @@ impl Tube {
                 // Prevents pour loops
                 new_self != other
             }
-            (a, b) if a == b => self.num_to_pour() <= other.num_free(),
+            (a, b) if a == b => match self.pour {
+                Pour::Whole => self.num_to_pour() <= other.num_free(),
+                Pour::Overflow | Pour::Single => other.num_free() > 0,
+            },
             (_, _) => false,
         }
     }
 
     pub fn pour_to(&mut self, other: &mut Self) {
         if let State::Water(colour) = self.top() {
-            for i in self.num_free()..(self.num_free() + self.num_to_pour()) {
+            let poured = match self.pour {
+                Pour::Single => 1,
+                Pour::Whole | Pour::Overflow => self.num_to_pour().min(other.num_free()),
+            };
+            for i in self.num_free()..(self.num_free() + poured) {
                 self.t[i] = State::Empty;
                 // This requires recalculation every time but it works better
                 other.t[other.num_free() - 1] = State::Water(colour);
             }
+            if other.is_complete() {
+                other.sticky = false;
+            }
+        }
+    }
+
+    /// Number of contiguous runs of a single colour
+    pub fn num_runs(self) -> usize {
+        let mut runs = 0;
+        let mut prev = State::Empty;
+        for &s in self.cells() {
+            if matches!(s, State::Water(_)) && s != prev {
+                runs += 1;
+            }
+            prev = s;
         }
+        runs
     }
 
     pub const fn top(self) -> State {
-        use State::Empty as E;
-        match (self.t[0], self.t[1], self.t[2], self.t[3]) {
-            (E, E, E, s) | (E, E, s, _) | (E, s, _, _) | (s, _, _, _) => s,
+        let free = self.num_free();
+        if free == self.capacity() {
+            State::Empty
+        } else {
+            self.t[free]
         }
     }
+
+    pub const fn bottom(self) -> State {
+        self.t[self.capacity() - 1]
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::state::State::{Empty, Unknown, Water};
-    use crate::water::Water::Blue;
+    use crate::water::BLUE;
 
     #[test]
     fn test_num_to_pour() {
         let mut a = Tube::empty();
 
-        a.set(0, Water(Blue));
-        a.set(1, Water(Blue));
-        a.set(2, Water(Blue));
-        a.set(3, Water(Blue));
+        a.set(0, Water(BLUE));
+        a.set(1, Water(BLUE));
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
 
         assert_eq!(a.num_to_pour(), 4);
     }
 
+    #[test]
+    fn test_capacity() {
+        let mut a = Tube::with_capacity(6);
+        assert_eq!(a.num_free(), 6);
+        assert_eq!(a.top(), Empty);
+
+        a.set(3, Water(BLUE));
+        a.set(4, Water(BLUE));
+        a.set(5, Unknown);
+        assert_eq!(a.num_free(), 3);
+        assert_eq!(a.num_to_pour(), 2);
+        assert_eq!(a.bottom(), Unknown);
+
+        let mut b = Tube::with_capacity(6);
+        b.set(5, Water(BLUE));
+        a.pour_to(&mut b);
+        assert_eq!(b.num_to_pour(), 3);
+        assert_eq!(b.cells().len(), 6);
+        assert!(!b.is_complete());
+    }
+
+    #[test]
+    fn test_sticky() {
+        let (mut a, mut b) = (Tube::empty(), Tube::empty());
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
+        b.set(3, Water(BLUE));
+        b.set(2, Water(BLUE));
+        b.set_sticky(true);
+        assert!(b.cannot_pour_to(a));
+        assert!(a.can_pour_to(b));
+
+        // Filling it with one colour unlocks it
+        a.pour_to(&mut b);
+        assert!(b.is_complete());
+        assert!(!b.is_sticky());
+    }
+
+    #[test]
+    fn test_num_runs() {
+        use crate::water::RED;
+        let mut a = Tube::empty();
+        assert_eq!(a.num_runs(), 0);
+
+        a.set(1, Water(BLUE));
+        a.set(2, Water(RED));
+        a.set(3, Water(RED));
+        assert_eq!(a.num_runs(), 2);
+
+        a.set(0, Water(RED));
+        a.set(1, Unknown);
+        assert_eq!(a.num_runs(), 2);
+    }
+
     #[test]
     fn test_num_free_full() {
         let mut a = Tube::empty();
 
-        a.set(0, Water(Blue));
-        a.set(1, Water(Blue));
-        a.set(2, Water(Blue));
-        a.set(3, Water(Blue));
+        a.set(0, Water(BLUE));
+        a.set(1, Water(BLUE));
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
 
         assert_eq!(a.num_free(), 0);
     }
@@ -130,9 +370,9 @@ mod tests {
     fn test_num_free_one() {
         let mut a = Tube::empty();
 
-        a.set(1, Water(Blue));
-        a.set(2, Water(Blue));
-        a.set(3, Water(Blue));
+        a.set(1, Water(BLUE));
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
 
         assert_eq!(a.num_free(), 1);
     }
@@ -140,88 +380,134 @@ mod tests {
     #[test]
     fn test_can_pour_to_empty() {
         let (mut a, b) = (Tube::empty(), Tube::empty());
-        a.set(2, Water(Blue));
+        a.set(2, Water(BLUE));
         a.set(3, Unknown);
         assert!(a.can_pour_to(b));
     }
 
     #[test]
     fn test_can_pour_to_one() {
-        use crate::water::Water::Blue;
+        use crate::water::BLUE;
         let (mut a, mut b) = (Tube::empty(), Tube::empty());
-        a.set(3, Water(Blue));
-        b.set(3, Water(Blue));
+        a.set(3, Water(BLUE));
+        b.set(3, Water(BLUE));
         assert!(a.can_pour_to(b));
     }
 
     #[test]
     fn test_cannot_pour_idempotent() {
-        use crate::water::Water::Blue;
+        use crate::water::BLUE;
         let (mut a, b) = (Tube::empty(), Tube::empty());
-        a.set(3, Water(Blue));
+        a.set(3, Water(BLUE));
         assert!(a.cannot_pour_to(b));
     }
 
     #[test]
     fn test_cannot_pour_to_full() {
-        use crate::water::Water::Blue;
+        use crate::water::BLUE;
         let (mut a, mut b) = (Tube::empty(), Tube::empty());
-        a.set(3, Water(Blue));
+        a.set(3, Water(BLUE));
 
-        b.set(0, Water(Blue));
-        b.set(1, Water(Blue));
-        b.set(2, Water(Blue));
-        b.set(3, Water(Blue));
+        b.set(0, Water(BLUE));
+        b.set(1, Water(BLUE));
+        b.set(2, Water(BLUE));
+        b.set(3, Water(BLUE));
 
         assert!(a.cannot_pour_to(b));
     }
 
-    /// Technically, in the game, you can still
-    /// pour to the brim and have some left over.
-    /// However, the state wouldn't really be
-    /// any different, so we just won't count that.
+    /// Technically, in some versions of the game, you can
+    /// still pour to the brim and have some left over.
+    /// That is [`Pour::Overflow`], not the default.
     #[test]
     fn test_cannot_pour_to_overflowing() {
         let (mut a, mut b) = (Tube::empty(), Tube::empty());
-        a.set(2, Water(Blue));
-        a.set(3, Water(Blue));
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
         b.set(0, Empty);
-        b.set(1, Water(Blue));
+        b.set(1, Water(BLUE));
         b.set(2, Unknown);
         b.set(3, Unknown);
         assert!(a.cannot_pour_to(b));
     }
 
+    #[test]
+    fn test_pour_to_overflowing() {
+        let (mut a, mut b) = (Tube::empty(), Tube::empty());
+        a.set(1, Water(BLUE));
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
+        b.set(2, Water(BLUE));
+        b.set(3, Unknown);
+        a.set_pour_rule(Pour::Overflow);
+        b.set_pour_rule(Pour::Overflow);
+        assert!(a.can_pour_to(b));
+
+        // Two of the three fit
+        a.pour_to(&mut b);
+        assert_eq!((a.num_to_pour(), a.num_free()), (1, 3));
+        assert_eq!((b.num_to_pour(), b.num_free()), (3, 0));
+        assert!(a.cannot_pour_to(b));
+        assert!(b.can_pour_to(a));
+
+        let mut c = Tube::empty();
+        c.set_pour_rule(Pour::Overflow);
+        c.set(3, Water(BLUE));
+        b.pour_to(&mut c);
+        assert_eq!((b.num_to_pour(), c.num_to_pour()), (0, 4));
+    }
+
+    #[test]
+    fn test_pour_single() {
+        let (mut a, mut b) = (Tube::empty(), Tube::empty());
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
+        a.set_pour_rule(Pour::Single);
+        b.set_pour_rule(Pour::Single);
+        assert!(a.can_pour_to(b));
+
+        a.pour_to(&mut b);
+        assert_eq!((a.num_to_pour(), b.num_to_pour()), (1, 1));
+        a.pour_to(&mut b);
+        assert_eq!((a.num_to_pour(), b.num_to_pour()), (0, 2));
+        assert!(b.can_pour_to(a));
+        // Moving the only cell to an empty tube changes nothing
+        let mut c = Tube::empty();
+        c.set_pour_rule(Pour::Single);
+        c.set(3, Water(BLUE));
+        assert!(c.cannot_pour_to(a));
+    }
+
     #[test]
     fn test_pour_to_empty() {
         let (mut a, mut b) = (Tube::empty(), Tube::empty());
 
-        a.set(3, Water(Blue));
+        a.set(3, Water(BLUE));
         a.pour_to(&mut b);
 
-        assert_eq!(b.t[3], Water(Blue));
+        assert_eq!(b.t[3], Water(BLUE));
     }
 
     #[test]
     fn test_pour_to_half() {
         let (mut a, mut b) = (Tube::empty(), Tube::empty());
 
-        a.set(2, Water(Blue));
-        a.set(3, Water(Blue));
+        a.set(2, Water(BLUE));
+        a.set(3, Water(BLUE));
 
         let to_pour = a.num_to_pour();
         a.pour_to(&mut b);
 
         assert_eq!(b.num_to_pour(), to_pour);
-        assert_eq!(b.t[2], Water(Blue));
-        assert_eq!(b.t[3], Water(Blue));
+        assert_eq!(b.t[2], Water(BLUE));
+        assert_eq!(b.t[3], Water(BLUE));
     }
 
     #[test]
     fn test_pour_one_to_empty() {
         let (mut a, mut b) = (Tube::empty(), Tube::empty());
 
-        a.set(0, Water(Blue));
+        a.set(0, Water(BLUE));
         a.set(1, Unknown);
         a.set(2, Unknown);
         a.set(3, Unknown);
@@ -230,6 +516,6 @@ mod tests {
         a.pour_to(&mut b);
 
         assert_eq!(b.num_to_pour(), to_pour);
-        assert_eq!(b.t[3], Water(Blue));
+        assert_eq!(b.t[3], Water(BLUE));
     }
 }
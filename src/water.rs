@@ -1,17 +1,30 @@
-#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Hash)]
-pub enum Water {
-    Ash,
-    Blue,
-    Brown,
-    Cyan,
-    Green,
-    Lime,
-    Olive,
-    Orange,
-    Pink,
-    Purple,
-    Red,
-    Yellow,
+use crate::palette;
+
+/// A colour of the current [`palette::Palette`], by its place in it
+#[derive(Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct Water(u8);
+
+#[cfg(test)]
+pub use builtin::*;
+
+/// The colours of the built-in palette. Only tests name
+/// them, everything else goes through the palette.
+#[cfg(test)]
+mod builtin {
+    use super::Water;
+
+    pub const ASH: Water = Water(0);
+    pub const BLUE: Water = Water(1);
+    pub const BROWN: Water = Water(2);
+    pub const CYAN: Water = Water(3);
+    pub const GREEN: Water = Water(4);
+    pub const LIME: Water = Water(5);
+    pub const OLIVE: Water = Water(6);
+    pub const ORANGE: Water = Water(7);
+    pub const PINK: Water = Water(8);
+    pub const PURPLE: Water = Water(9);
+    pub const RED: Water = Water(10);
+    pub const YELLOW: Water = Water(11);
 }
 
 pub enum ParseError {
@@ -39,21 +52,9 @@ impl TryFrom<&str> for Water {
     type Error = ParseError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "a" | "ash" | "G" | "grey" | "gray" | "gr" => Ok(Self::Ash),
-            "b" | "bl" | "blue" => Ok(Self::Blue),
-            "B" | "br" | "brown" => Ok(Self::Brown),
-            "c" | "cy" | "cyan" => Ok(Self::Cyan),
-            "g" | "green" => Ok(Self::Green),
-            "l" | "lime" => Ok(Self::Lime),
-            "O" | "ol" | "olive" => Ok(Self::Olive),
-            "o" | "or" => Ok(Self::Orange),
-            "p" | "pi" | "pink" => Ok(Self::Pink),
-            "P" | "pu" | "purple" => Ok(Self::Purple),
-            "r" | "red" => Ok(Self::Red),
-            "y" | "yellow" => Ok(Self::Yellow),
-            s => Err(Self::Error::UnknownColour(s.to_owned())),
-        }
+        palette::current()
+            .find(value)
+            .ok_or_else(|| Self::Error::UnknownColour(value.to_owned()))
     }
 }
 
@@ -66,30 +67,88 @@ impl TryFrom<&&str> for Water {
 }
 
 impl Water {
-    const fn get_colour(self) -> ansi_term::Colour {
-        use ansi_term::Colour::RGB;
-        match self {
-            Self::Blue => RGB(58, 46, 195),
-            Self::Brown => RGB(126, 74, 7),
-            Self::Cyan => RGB(84, 163, 228),
-            Self::Green => RGB(17, 101, 51),
-            Self::Ash => RGB(99, 100, 101),
-            Self::Lime => RGB(98, 214, 124),
-            Self::Olive => RGB(120, 150, 15),
-            Self::Orange => RGB(232, 140, 66),
-            Self::Pink => RGB(234, 94, 123),
-            Self::Purple => RGB(113, 43, 147),
-            Self::Red => RGB(197, 42, 35),
-            Self::Yellow => RGB(241, 217, 87),
+    pub const fn from_index(i: usize) -> Self {
+        assert!(i < palette::MAX_COLOURS);
+        Self(i as u8)
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Every colour of the current palette
+    pub fn all() -> Vec<Self> {
+        palette::current().colours().map(|(w, _)| w).collect()
+    }
+
+    /// Its name in the current palette, if it has one
+    pub fn name(self) -> Option<String> {
+        palette::current().get(self).map(|c| c.name.clone())
+    }
+}
+
+/// The name, so it reads the same in messages as when typed
+impl std::fmt::Debug for Water {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "Colour{}", self.0),
         }
     }
 }
 
 impl std::fmt::Display for Water {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        ansi_term::Style::new()
-            .on(self.get_colour())
-            .paint("   ")
-            .fmt(f)
+        use ansi_term::{Colour::RGB, Style};
+        match palette::current().get(*self) {
+            Some(c) => Style::new()
+                .on(RGB(c.rgb[0], c.rgb[1], c.rgb[2]))
+                .paint("   ")
+                .fmt(f),
+            // Left behind by switching to a smaller palette
+            None => Style::new().on(RGB(0, 0, 0)).paint(" ! ").fmt(f),
+        }
+    }
+}
+
+/// Saved by name, so puzzles still load if the palette is reordered
+impl serde::Serialize for Water {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = self.name().ok_or_else(|| {
+            serde::ser::Error::custom(format!("colour {} is not in the palette", self.0))
+        })?;
+        serializer.serialize_str(&name)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Water {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        palette::current()
+            .find(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown colour {name}")))
+    }
+}
+
+#[cfg(test)]
+mod water_test {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        assert!(matches!(Water::try_from("o"), Ok(ORANGE)));
+        assert!(matches!(
+            Water::try_from("x"),
+            Err(ParseError::UnknownColour(_))
+        ));
+        assert_eq!(format!("{PURPLE:?}"), "Purple");
+        assert_eq!(
+            Water::all(),
+            [ASH, BLUE, BROWN, CYAN, GREEN, LIME, OLIVE, ORANGE, PINK, PURPLE, RED, YELLOW]
+        );
+
+        assert_eq!(serde_json::to_string(&RED).unwrap(), r#""Red""#);
+        assert_eq!(serde_json::from_str::<Water>(r#""Cyan""#).unwrap(), CYAN);
+        assert!(serde_json::from_str::<Water>(r#""Teal""#).is_err());
     }
 }